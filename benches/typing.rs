@@ -0,0 +1,71 @@
+//! Benchmarks of the line editor on a large buffer
+//!
+//! The editor and parser do not depend on the rest of the shell, so they are
+//! compiled in directly from the sources.
+
+#[path = "../src/parser/mod.rs"]
+#[allow(dead_code)]
+mod parser;
+#[path = "../src/input.rs"]
+#[allow(dead_code)]
+mod input;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use input::Input;
+
+/// Builds a script of about `size` bytes
+fn script(size: usize) -> String {
+    let lines = [
+        "for file in *.rs; do wc -l \"$file\" > /dev/null 2>&1; done",
+        "if test -d \"$HOME/src\"; then cd \"$HOME/src\" && ls -la | grep rush; fi",
+        "CC=gcc make -j4 all >> build.log || echo 'build failed' >&2",
+        "greet() { echo \"hello ${USER}\"; }",
+    ];
+    let mut script = String::new();
+    for line in lines.iter().cycle() {
+        if script.len() >= size {
+            break;
+        }
+        script.push_str(line);
+        script.push('\n');
+    }
+    script
+}
+
+fn typing(c: &mut Criterion) {
+    let script = script(10 * 1024);
+
+    c.bench_function("type 10 KB", |b| b.iter(|| {
+        let mut input = Input::new();
+        for c in script.chars() {
+            input.push(c);
+        }
+        black_box(input.get(true).is_ok());
+    }));
+
+    c.bench_function("edit middle of 10 KB", |b| {
+        let mut input = Input::new();
+        input.set(&script);
+        for _ in 0..script.len() / 2 {
+            input.left();
+        }
+        b.iter(|| {
+            for c in "echo x; ".chars() {
+                input.push(c);
+            }
+            for _ in 0..8 {
+                input.pop_prev();
+            }
+        });
+    });
+
+    c.bench_function("parse 10 KB", |b| {
+        let mut input = Input::new();
+        input.set(&script);
+        b.iter(|| black_box(input.get(true).is_ok()));
+    });
+}
+
+criterion_group!(benches, typing);
+criterion_main!(benches);
@@ -1,50 +1,1187 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::Write;
+use std::os::unix::io::RawFd;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use nix::errno::{self, Errno};
+use nix::poll::{self, PollFd, PollFlags};
+use nix::sys::termios::{self, LocalFlags, SetArg, SpecialCharacterIndices};
+use nix::sys::stat::{self, Mode};
 use nix::unistd;
 
+use super::condition;
 use super::config::Config;
-use super::SHELL;
+use super::executor::ExecuteeKind;
+use super::expand;
+use super::shell::{Shell, OPTIONS};
+use super::format::{self, Escapes};
+use super::value::{self, Value};
+
+type Builtin = fn(&[String]) -> u8;
+
+/// Registry of builtins
+///
+/// Strong builtins run in the shell process itself, either because they
+/// change the state of the shell or because they are common enough for a
+/// fork to matter. Weak builtins run in a forked child like binaries.
+const BUILTINS: &[(&str, bool, Builtin)] = &[
+    ("cd", true, cd),
+    ("pushd", true, pushd),
+    ("popd", true, popd),
+    ("dirs", true, dirs),
+    ("z", true, z),
+    ("exit", true, exit),
+    ("exec", true, exec),
+    ("trap", true, trap),
+    ("source", true, source),
+    (".", true, source),
+    ("shift", true, shift),
+    ("set", true, set),
+    ("declare", true, declare),
+    ("export", true, export),
+    ("unset", true, unset),
+    ("read", true, read),
+    ("printf", true, printf),
+    ("echo", true, echo),
+    ("type", true, describe),
+    ("command", true, command),
+    ("umask", true, umask),
+    ("alias", true, alias),
+    ("unalias", true, unalias),
+    (":", true, colon),
+    ("test", true, test),
+    ("[", true, test),
+    ("state", false, state),
+    ("self", false, state),
+];
+
+/// Returns the kind of builtin `name`, if there is one
+pub fn kind(name: &str) -> Option<ExecuteeKind> {
+    BUILTINS.iter()
+        .find(|builtin| builtin.0 == name)
+        .map(|builtin| if builtin.1 {
+            ExecuteeKind::StrongBuiltin(String::from(name))
+        } else {
+            ExecuteeKind::WeakBuiltin(String::from(name))
+        })
+}
+
+/// Runs builtin `name` with `args`
+pub fn run(name: &str, args: &[String]) -> u8 {
+    match name {
+        "fail" => fail(args),
+        "void" => void(),
+        name => BUILTINS.iter()
+            .find(|builtin| builtin.0 == name)
+            .map(|builtin| (builtin.2)(args))
+            .unwrap_or(1),
+    }
+}
 
 pub fn cd(args: &[String]) -> u8 {
-    let path = match args.len() {
-        1 => {
-            eprintln!("cd: Not enough arguments.");
+    let shell = super::shell();
+
+    let mut physical = false;
+    let mut operands = args.iter().skip(1);
+    let mut operand = None;
+    for arg in &mut operands {
+        match arg.as_str() {
+            "--" => {
+                operand = operands.next();
+                break;
+            },
+            "-" => {
+                operand = Some(arg);
+                break;
+            },
+            opts if opts.starts_with('-') => {
+                for opt in opts.chars().skip(1) {
+                    match opt {
+                        'L' => physical = false,
+                        'P' => physical = true,
+                        _ => {
+                            error!("cd: Invalid option -{}.", opt);
+                            return 1;
+                        },
+                    }
+                }
+            },
+            _ => {
+                operand = Some(arg);
+                break;
+            },
+        }
+    }
+    if operands.next().is_some() {
+        error!("cd: Too many arguments.");
+        return 1;
+    }
+
+    let (target, print) = match operand.map(String::as_str) {
+        None => match shell.var("HOME").filter(|home| !home.is_empty()) {
+            Some(home) => (home.clone(), false),
+            None => {
+                error!("cd: HOME not set.");
+                return 1;
+            },
+        },
+        Some("-") => match shell.var("OLDPWD").filter(|old| !old.is_empty()) {
+            Some(old) => (old.clone(), true),
+            None => {
+                error!("cd: OLDPWD not set.");
+                return 1;
+            },
+        },
+        Some(dir) => (String::from(dir), false),
+    };
+
+    let (path, found) = search_cdpath(&target, shell.var("CDPATH").map(String::as_str));
+    let oldpwd = shell.var("PWD").cloned();
+    let retcode = change_dir(&path, physical, print || found);
+    if retcode == 0 && shell.config().auto_pushd {
+        if let Some(oldpwd) = oldpwd {
+            shell.dir_stack_mut().insert(0, oldpwd);
+        }
+    }
+    retcode
+}
+
+/// Looks `target` up in the colon separated `cdpath`
+///
+/// Returns the path to change into and whether it was found through a
+/// non-empty `CDPATH` entry, in which case cd reports the new directory.
+fn search_cdpath(target: &str, cdpath: Option<&str>) -> (PathBuf, bool) {
+    let relative = !target.starts_with('/')
+        && !matches!(Path::new(target).components().next(), Some(Component::CurDir) | Some(Component::ParentDir));
+    if let (true, Some(cdpath)) = (relative, cdpath) {
+        for entry in cdpath.split(':') {
+            let candidate = if entry.is_empty() {
+                Path::new(".").join(target)
+            } else {
+                Path::new(entry).join(target)
+            };
+            if candidate.is_dir() {
+                return (candidate, !entry.is_empty());
+            }
+        }
+    }
+    (PathBuf::from(target), false)
+}
+
+/// Resolves `.` and `..` components of `path` without touching the filesystem
+///
+/// A relative `path` is taken relative to `pwd`.
+fn logical_path(pwd: &Path, path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::from("/");
+    if path.is_relative() {
+        resolved.push(pwd);
+    }
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            },
+            Component::Normal(name) => resolved.push(name),
+            _ => (),
+        }
+    }
+    resolved
+}
+
+/// Changes the working directory to `path` and updates `PWD` and `OLDPWD`
+///
+/// With `physical` set, symbolic links are resolved before `..` is
+/// processed and `PWD` is set to the physical directory. With `print` set,
+/// the new directory is written to standard output.
+pub fn change_dir(path: &Path, physical: bool, print: bool) -> u8 {
+    let shell = super::shell();
+
+    let oldpwd = shell.var("PWD")
+        .map(PathBuf::from)
+        .filter(|pwd| pwd.is_absolute())
+        .or_else(|| unistd::getcwd().ok());
+    let curpath = match (&oldpwd, physical) {
+        (Some(pwd), false) => logical_path(pwd, path),
+        _ => path.to_path_buf(),
+    };
+
+    if unistd::chdir(&curpath).is_err() {
+        let display = curpath.to_string_lossy();
+        match errno::Errno::last() {
+            Errno::EACCES => error!("cd: Search permission denied."),
+            Errno::EFAULT => error!("cd: Path \"{}\" points outside accessible address space.", display),
+            Errno::EIO => error!("cd: And I/O error occurred."),
+            Errno::ELOOP => error!("cd: Too many symbolic links encountered."),
+            Errno::ENAMETOOLONG => error!("cd: Path is too long."),
+            Errno::ENOENT => error!("cd: The directory \"{}\" does not exist.", display),
+            Errno::ENOMEM => error!("cd: Insufficient kernel memory."),
+            Errno::ENOTDIR => error!("cd: \"{}\" is not a directory.", display),
+            _ => error!("cd: Unknown error."),
+        };
+        return 1;
+    }
+
+    let pwd = if physical {
+        unistd::getcwd().unwrap_or(curpath)
+    } else {
+        curpath
+    };
+    let pwd = pwd.to_string_lossy().into_owned();
+    if print {
+        println!("{}", pwd);
+    }
+
+    if let Some(oldpwd) = oldpwd {
+        shell.set_var("OLDPWD", oldpwd.to_string_lossy().into_owned());
+        shell.export("OLDPWD");
+    }
+    if let Some(frecency) = shell.frecency() {
+        if shell.var("HOME") != Some(&pwd) {
+            frecency.add(&pwd);
+        }
+    }
+    shell.set_var("PWD", pwd);
+    shell.export("PWD");
+    0
+}
+
+/// Parses a `+N` or `-N` directory stack index into a position from the top
+/// of a stack of `len` entries
+fn stack_index(arg: &str, len: usize) -> Option<usize> {
+    let n: usize = arg.get(1..)?.parse().ok()?;
+    if n >= len {
+        return None;
+    }
+    match arg.chars().next()? {
+        '+' => Some(n),
+        '-' => Some(len - 1 - n),
+        _ => None,
+    }
+}
+
+/// Returns the full directory stack with the current directory on top
+fn full_stack() -> Vec<String> {
+    let shell = super::shell();
+    let pwd = shell.var("PWD").cloned().unwrap_or_default();
+    std::iter::once(pwd).chain(shell.dir_stack().iter().cloned()).collect()
+}
+
+pub fn pushd(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let mut stack = full_stack();
+    let arg = match args.len() {
+        1 => None,
+        2 => Some(args[1].as_str()),
+        _ => {
+            error!("pushd: Too many arguments.");
             return 1;
         },
-        2 => args[1].as_str(),
+    };
+
+    match arg {
+        None => {
+            if stack.len() < 2 {
+                error!("pushd: No other directory.");
+                return 1;
+            }
+            stack.swap(0, 1);
+        },
+        Some(arg) if arg.len() > 1 && (arg.starts_with('+') || arg.starts_with('-')) => {
+            match stack_index(arg, stack.len()) {
+                Some(n) => stack.rotate_left(n),
+                None => {
+                    error!("pushd: {}: Directory stack index out of range.", arg);
+                    return 1;
+                },
+            }
+        },
+        Some(dir) => {
+            let (path, _) = search_cdpath(dir, shell.var("CDPATH").map(String::as_str));
+            if change_dir(&path, false, false) != 0 {
+                return 1;
+            }
+            shell.dir_stack_mut().insert(0, stack.remove(0));
+            return dirs(&args[..1]);
+        },
+    }
+
+    if change_dir(Path::new(&stack[0]), false, false) != 0 {
+        return 1;
+    }
+    *shell.dir_stack_mut() = stack.split_off(1);
+    dirs(&args[..1])
+}
+
+pub fn popd(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let mut stack = full_stack();
+    if stack.len() < 2 {
+        error!("popd: Directory stack empty.");
+        return 1;
+    }
+
+    let n = match args.len() {
+        1 => 0,
+        2 => match stack_index(&args[1], stack.len()) {
+            Some(n) => n,
+            None => {
+                error!("popd: {}: Directory stack index out of range.", args[1]);
+                return 1;
+            },
+        },
         _ => {
-            eprintln!("cd: Too many arguments.");
+            error!("popd: Too many arguments.");
+            return 1;
+        },
+    };
+
+    stack.remove(n);
+    if n == 0 && change_dir(Path::new(&stack[0]), false, false) != 0 {
+        return 1;
+    }
+    *shell.dir_stack_mut() = stack.split_off(1);
+    dirs(&args[..1])
+}
+
+pub fn dirs(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let mut verbose = false;
+    let mut long = false;
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "-c" => {
+                shell.dir_stack_mut().clear();
+                return 0;
+            },
+            "-v" => verbose = true,
+            "-l" => long = true,
+            _ => {
+                error!("dirs: Invalid option {}.", arg);
+                return 1;
+            },
+        }
+    }
+
+    let home = shell.var("HOME").filter(|home| !home.is_empty() && !long);
+    let stack: Vec<_> = full_stack().into_iter()
+        .map(|dir| match home {
+            Some(home) if dir == *home => String::from("~"),
+            Some(home) if dir.starts_with(&format!("{}/", home)) => format!("~{}", &dir[home.len()..]),
+            _ => dir,
+        })
+        .collect();
+
+    if verbose {
+        for (i, dir) in stack.iter().enumerate() {
+            println!("{:2}  {}", i, dir);
+        }
+    } else {
+        println!("{}", stack.join(" "));
+    }
+    0
+}
+
+pub fn z(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let frecency = match shell.frecency() {
+        Some(frecency) => frecency,
+        None => {
+            error!("z: Directory tracking is disabled.");
             return 1;
+        },
+    };
+
+    let list = args.get(1).map(String::as_str) == Some("-l");
+    let fragments = &args[if list { 2 } else { 1 }..];
+    let matches = frecency.query(fragments);
+
+    if list || fragments.is_empty() {
+        for (score, path) in matches.iter().rev() {
+            println!("{:<10.1} {}", score, path);
         }
+        return 0;
+    }
+
+    let pwd = shell.var("PWD");
+    match matches.iter().find(|(_, path)| Some(path) != pwd) {
+        Some((_, path)) => change_dir(Path::new(path), false, false),
+        None => {
+            error!("z: No match for {}.", fragments.join(" "));
+            1
+        },
+    }
+}
+
+pub fn source(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let file = match args.get(1) {
+        Some(file) => file,
+        None => {
+            error!("{}: Filename argument required.", args[0]);
+            return 2;
+        },
     };
 
-    if unistd::chdir(path).is_err() {
-        match errno::Errno::last() {
-            Errno::EACCES => eprintln!("cd: Search permission denied."),
-            Errno::EFAULT => eprintln!("cd: Path \"{}\" points outside accessible address space.", &args[1]),
-            Errno::EIO => eprintln!("cd: And I/O error occurred."),
-            Errno::ELOOP => eprintln!("cd: Too many symbolic links encountered."),
-            Errno::ENAMETOOLONG => eprintln!("cd: Path is too long."),
-            Errno::ENOENT => eprintln!("cd: The directory \"{}\" does not exist.", &args[1]),
-            Errno::ENOMEM => eprintln!("cd: Insufficient kernel memory."),
-            Errno::ENOTDIR => eprintln!("cd: \"{}\" is not a directory.", &args[1]),
-            _ => eprintln!("cd: Unknown error."),
+    let path = if file.contains('/') {
+        PathBuf::from(file)
+    } else {
+        shell.bin_dirs().iter()
+            .map(|dir| Path::new(dir).join(file))
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| PathBuf::from(file))
+    };
+
+    let positional = if args.len() > 2 {
+        Some(shell.set_positional(args[2..].to_vec()))
+    } else {
+        None
+    };
+    let retcode = shell.run_file(&path);
+    if let Some(positional) = positional {
+        shell.set_positional(positional);
+    }
+
+    match retcode {
+        Some(retcode) => retcode as u8,
+        None => {
+            error!("{}: Could not read file {}.", args[0], file);
+            1
+        },
+    }
+}
+
+pub fn shift(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let n = match args.get(1).map(|n| n.parse::<usize>()) {
+        None => 1,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            error!("shift: {}: Numeric argument required.", args[1]);
+            return 1;
+        },
+    };
+
+    let mut positional = shell.positional().clone();
+    if n > positional.len() {
+        error!("shift: Shift count out of range.");
+        return 1;
+    }
+    positional.drain(..n);
+    shell.set_positional(positional);
+    0
+}
+
+/// Sets options and positional parameters
+///
+/// Options are turned on with `-` and off with `+`, either by letter or
+/// by name after `o`. `-o` and `+o` alone list them, the latter as
+/// commands that set them again. Arguments after the options, or after
+/// `--`, become the positional parameters. Without arguments, prints the
+/// variables.
+pub fn set(args: &[String]) -> u8 {
+    let shell = super::shell();
+    if args.len() == 1 {
+        state_vars(shell.vars());
+        return 0;
+    }
+    let mut args = args[1..].iter();
+    let mut positional = None;
+    while let Some(arg) = args.next() {
+        let (on, flags) = match (arg.strip_prefix('-'), arg.strip_prefix('+')) {
+            (Some("-"), _) => {
+                positional = Some(args.cloned().collect());
+                break;
+            },
+            (Some(flags), _) if !flags.is_empty() => (true, flags),
+            (_, Some(flags)) if !flags.is_empty() => (false, flags),
+            _ => {
+                positional = Some(std::iter::once(arg).chain(args).cloned().collect());
+                break;
+            },
+        };
+        for flag in flags.chars() {
+            let flag = match flag {
+                'o' => match args.next() {
+                    Some(name) => match OPTIONS.iter().find(|option| option.0 == name) {
+                        Some(option) => option.1,
+                        None => {
+                            error!("set: {}: Invalid option name.", name);
+                            return 2;
+                        },
+                    },
+                    None => {
+                        print_options(shell, !on);
+                        continue;
+                    },
+                },
+                flag if OPTIONS.iter().any(|option| option.1 == flag) => flag,
+                flag => {
+                    error!("set: Invalid option {}{}.", if on { '-' } else { '+' }, flag);
+                    return 2;
+                },
+            };
+            shell.set_flag(flag, on);
+        }
+    }
+    if let Some(positional) = positional {
+        shell.set_positional(positional);
+    }
+    0
+}
+
+fn print_options(shell: &Shell, commands: bool) {
+    for (name, flag) in OPTIONS {
+        let on = shell.flag(*flag);
+        if commands {
+            println!("set {}o {}", if on { '-' } else { '+' }, name);
+        } else {
+            println!("{:<15} {}", name, if on { "on" } else { "off" });
+        }
+    }
+}
+
+/// Declares variables, `-a` and `-A` making them indexed and associative
+/// arrays and `-x` exporting them
+///
+/// Without names, or with `-p`, prints the variables instead.
+pub fn declare(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let mut array = None;
+    let mut export = false;
+    let mut print = false;
+    let mut names = Vec::new();
+    for arg in &args[1..] {
+        match arg.strip_prefix('-') {
+            Some(flags) if names.is_empty() && !flags.is_empty() => {
+                for flag in flags.chars() {
+                    match flag {
+                        'a' => array = Some(Value::Indexed(Default::default())),
+                        'A' => array = Some(Value::Associative(Default::default())),
+                        'x' => export = true,
+                        'p' => print = true,
+                        _ => {
+                            error!("declare: Invalid option -{}.", flag);
+                            return 2;
+                        },
+                    }
+                }
+            },
+            _ => names.push(arg),
+        }
+    }
+
+    if print || names.is_empty() {
+        if names.is_empty() {
+            state_vars(shell.vars());
+        }
+        let mut retcode = 0;
+        for name in names {
+            match shell.value(name) {
+                Some(value) => print_var(name, value),
+                None => {
+                    error!("declare: {} not found.", name);
+                    retcode = 1;
+                },
+            }
+        }
+        return retcode;
+    }
+
+    for arg in names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !valid_name(name) {
+            error!("declare: Invalid name {}.", name);
+            return 1;
+        }
+        if let Some(array) = &array {
+            shell.declare_array(name, array.clone());
+        }
+        if let Some(value) = value {
+            shell.set_var(name, String::from(value));
+        } else if shell.value(name).is_none() && array.is_none() {
+            shell.set_var(name, String::new());
+        }
+        if export {
+            shell.export(name);
+        }
+    }
+    0
+}
+
+/// Exports variables given by name or as `name=value`, assigning those
+///
+/// Without names, or with `-p`, prints the exported variables instead.
+pub fn export(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let names = match &args[1..] {
+        [print, names @ ..] if print == "-p" => names,
+        names => names,
+    };
+    if names.is_empty() {
+        let mut exports: Vec<_> = shell.exports().iter().collect();
+        exports.sort();
+        for name in exports {
+            match shell.var(name) {
+                Some(value) => println!("export {}={}", name, value::quote(value)),
+                None => println!("export {}", name),
+            }
+        }
+        return 0;
+    }
+
+    let mut retcode = 0;
+    for arg in names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
         };
-        1
+        if !valid_name(name) {
+            error!("export: {}: Invalid name.", name);
+            retcode = 1;
+            continue;
+        }
+        if let Some(value) = value {
+            shell.set_var(name, String::from(value));
+        }
+        shell.export(name);
+    }
+    retcode
+}
+
+/// Removes variables, or functions with `-f`
+///
+/// With `-v` or no option, names are taken as variables only.
+pub fn unset(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let (functions, names) = match &args[1..] {
+        [option, names @ ..] if option == "-f" => (true, names),
+        [option, names @ ..] if option == "-v" => (false, names),
+        names => (false, names),
+    };
+    let mut retcode = 0;
+    for name in names {
+        if functions {
+            shell.unset_function(name);
+        } else if valid_name(name) {
+            shell.unset_var(name);
+        } else {
+            error!("unset: {}: Invalid name.", name);
+            retcode = 1;
+        }
+    }
+    retcode
+}
+
+/// Checks whether `name` can name a variable
+pub fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Writes `text` to standard output, which may be a closed pipe or closed
+/// altogether
+///
+/// The descriptor is written directly, as `Stdout` takes a closed one for
+/// a sink.
+fn write_out(name: &str, text: &str) -> u8 {
+    std::io::stdout().flush().unwrap_or(());
+    let mut bytes = text.as_bytes();
+    while !bytes.is_empty() {
+        match unistd::write(1, bytes) {
+            Ok(written) => bytes = &bytes[written..],
+            Err(Errno::EINTR) => continue,
+            Err(error) => {
+                error!("{}: Write error: {}.", name, error.desc());
+                return 1;
+            },
+        }
+    }
+    0
+}
+
+/// Prints its arguments separated by spaces
+///
+/// `-n` leaves out the trailing newline and `-e` turns on backslash
+/// escapes, which `-E` turns off again.
+pub fn echo(args: &[String]) -> u8 {
+    let mut newline = true;
+    let mut escapes = false;
+    let mut words = &args[1..];
+    while let Some((first, rest)) = words.split_first() {
+        match first.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() && flags.chars().all(|flag| "neE".contains(flag)) => {
+                for flag in flags.chars() {
+                    match flag {
+                        'n' => newline = false,
+                        'e' => escapes = true,
+                        _ => escapes = false,
+                    }
+                }
+                words = rest;
+            },
+            _ => break,
+        }
+    }
+
+    let mut output = words.join(" ");
+    if escapes {
+        let (text, stop) = format::unescape(&output, Escapes::Echo);
+        output = text;
+        newline &= !stop;
+    }
+    if newline {
+        output.push('\n');
+    }
+    write_out("echo", &output)
+}
+
+/// Prints its arguments according to a format, or with `-v` assigns the
+/// result to a variable
+pub fn printf(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let mut args = &args[1..];
+    let mut var = None;
+    if let [option, name, rest @ ..] = args {
+        if option == "-v" {
+            if !valid_name(name) {
+                error!("printf: {}: Invalid variable name.", name);
+                return 2;
+            }
+            var = Some(name);
+            args = rest;
+        }
+    }
+    if args.first().is_some_and(|arg| arg == "--") {
+        args = &args[1..];
+    }
+    let (format, args) = match args.split_first() {
+        Some(split) => split,
+        None => {
+            error!("printf: Missing format.");
+            return 2;
+        },
+    };
+
+    match format::printf(format, args) {
+        Ok((output, converted)) => {
+            let retcode = match var {
+                Some(name) => {
+                    shell.set_var(name, output);
+                    0
+                },
+                None => write_out("printf", &output),
+            };
+            retcode.max(!converted as u8)
+        },
+        Err(message) => {
+            error!("printf: {}", message);
+            1
+        },
+    }
+}
+
+/// Reads a line from standard input into variables
+///
+/// The line is split on IFS, the last variable taking what is left of it,
+/// all fields go to an array with `-a` and the whole line to `REPLY` if no
+/// variable is named. Unless `-r` is given, backslashes escape the next
+/// character and join lines. `-d` sets the delimiter ending the line, `-n`
+/// the number of characters to read at most, `-t` a timeout in seconds,
+/// `-p` a prompt shown on terminals and `-s` turns off echoing input.
+pub fn read(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let mut raw = false;
+    let mut silent = false;
+    let mut prompt = None;
+    let mut timeout = None;
+    let mut count = None;
+    let mut delimiter = b'\n';
+    let mut array = None;
+    let mut names = Vec::new();
+
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        let flags = match arg.strip_prefix('-') {
+            Some(flags) if names.is_empty() && !flags.is_empty() => flags,
+            _ => {
+                names.push(arg);
+                continue;
+            },
+        };
+        for (offset, flag) in flags.char_indices() {
+            match flag {
+                'r' => raw = true,
+                's' => silent = true,
+                'p' | 't' | 'n' | 'd' | 'a' => {
+                    let rest = &flags[offset + 1..];
+                    let value = match args.next().filter(|_| rest.is_empty()) {
+                        Some(value) => value.as_str(),
+                        None if !rest.is_empty() => rest,
+                        None => {
+                            error!("read: -{}: Option requires an argument.", flag);
+                            return 2;
+                        },
+                    };
+                    let valid = match flag {
+                        'p' => {
+                            prompt = Some(value);
+                            true
+                        },
+                        't' => {
+                            timeout = value.parse::<f64>().ok().filter(|timeout| *timeout >= 0.0);
+                            timeout.is_some()
+                        },
+                        'n' => {
+                            count = value.parse::<usize>().ok();
+                            count.is_some()
+                        },
+                        'd' => {
+                            delimiter = value.bytes().next().unwrap_or(0);
+                            true
+                        },
+                        _ => {
+                            array = Some(value);
+                            valid_name(value)
+                        },
+                    };
+                    if !valid {
+                        error!("read: {}: Invalid argument to -{}.", value, flag);
+                        return 2;
+                    }
+                    break;
+                },
+                _ => {
+                    error!("read: Invalid option -{}.", flag);
+                    return 2;
+                },
+            }
+        }
+    }
+    if let Some(name) = names.iter().find(|name| !valid_name(name)) {
+        error!("read: {}: Invalid variable name.", name);
+        return 2;
+    }
+
+    // A zero timeout only checks whether there is input.
+    if timeout == Some(0.0) {
+        let mut fds = [PollFd::new(0, PollFlags::POLLIN)];
+        return !poll::poll(&mut fds, 0).is_ok_and(|ready| ready > 0) as u8;
+    }
+
+    let terminal = unistd::isatty(0).unwrap_or(false);
+    if let (Some(prompt), true) = (prompt, terminal) {
+        eprint!("{}", prompt);
+    }
+    // A terminal hands over input line by line unless told otherwise,
+    // which would not do for a character count or another delimiter.
+    let canonical = count.is_none() && delimiter == b'\n';
+    let saved = match termios::tcgetattr(0) {
+        Ok(attributes) if silent || !canonical => {
+            let mut changed = attributes.clone();
+            if silent {
+                changed.local_flags.remove(LocalFlags::ECHO);
+            }
+            if !canonical {
+                changed.local_flags.remove(LocalFlags::ICANON);
+                changed.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+                changed.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+            }
+            let _ = termios::tcsetattr(0, SetArg::TCSADRAIN, &changed);
+            Some(attributes)
+        },
+        _ => None,
+    };
+    let (line, retcode) = read_line(delimiter, raw, count, timeout);
+    if let Some(attributes) = saved {
+        let _ = termios::tcsetattr(0, SetArg::TCSADRAIN, &attributes);
+    }
+
+    let ifs = expand::ifs(shell);
+    if let Some(array) = array {
+        let fields = expand::split_line(&line, &ifs, usize::MAX, raw);
+        shell.set_value(array, Value::Indexed(fields.into_iter().enumerate().collect()));
+    } else if names.is_empty() {
+        let reply = expand::split_line(&line, "", 1, raw).pop().unwrap_or_default();
+        shell.set_var("REPLY", reply);
+    } else {
+        let mut fields = expand::split_line(&line, &ifs, names.len(), raw).into_iter();
+        for name in names {
+            shell.set_var(name, fields.next().unwrap_or_default());
+        }
+    }
+    retcode
+}
+
+/// Reads standard input up to `delimiter` or `count` characters
+///
+/// Input is read byte by byte so as to leave the rest of it to whoever
+/// reads next. Unless `raw`, an escaped delimiter does not end the line
+/// and an escaped newline is dropped. Returns the line and 0, or 1 on end
+/// of input, or 142 when `timeout` seconds pass first.
+fn read_line(delimiter: u8, raw: bool, count: Option<usize>, timeout: Option<f64>) -> (String, u8) {
+    let deadline = timeout.map(|timeout| Instant::now() + Duration::from_secs_f64(timeout));
+    let mut buffer = Vec::new();
+    let mut escaped = false;
+    let mut chars = 0;
+    let mut byte = [0u8];
+    let retcode = loop {
+        if count.is_some_and(|count| chars >= count) {
+            break 0;
+        }
+        if let Some(deadline) = deadline {
+            let left = deadline.saturating_duration_since(Instant::now()).as_millis();
+            let mut fds = [PollFd::new(0, PollFlags::POLLIN)];
+            match poll::poll(&mut fds, left.min(i32::MAX as u128) as i32) {
+                Ok(0) => break 142,
+                Err(Errno::EINTR) => continue,
+                _ => (),
+            }
+        }
+        match unistd::read(0, &mut byte) {
+            Ok(1) => (),
+            Err(Errno::EINTR) => continue,
+            _ => break 1,
+        }
+        let byte = byte[0];
+        if byte == delimiter && !escaped {
+            break 0;
+        }
+        if escaped && byte == b'\n' {
+            buffer.pop();
+            escaped = false;
+            continue;
+        }
+        escaped = !raw && byte == b'\\' && !escaped;
+        buffer.push(byte);
+        // Continuation bytes of UTF-8 do not start another character.
+        if !escaped && byte & 0xc0 != 0x80 {
+            chars += 1;
+        }
+    };
+    (String::from_utf8_lossy(&buffer).into_owned(), retcode)
+}
+
+/// Reserved words of the shell grammar
+const KEYWORDS: &[&str] = &[
+    "if", "then", "elif", "else", "fi", "while", "until", "for", "in", "do", "done", "{", "}", "!", "[[", "]]",
+];
+
+/// What a name stands for as a command
+enum Meaning {
+    Alias(String),
+    Keyword,
+    Command(ExecuteeKind),
+}
+
+/// Looks up what `name` stands for as a command, all meanings it has if
+/// `all` and otherwise just the one that takes effect
+fn meanings(name: &str, all: bool) -> Vec<Meaning> {
+    let shell = super::shell();
+    let alias = shell.aliases().get(name).map(|value| Meaning::Alias(value.clone()));
+    let keyword = KEYWORDS.contains(&name).then_some(Meaning::Keyword);
+    let commands = shell.candidates(name).map(Meaning::Command);
+    let meanings = alias.into_iter().chain(keyword).chain(commands);
+    if all {
+        meanings.collect()
     } else {
-        0
+        meanings.take(1).collect()
+    }
+}
+
+/// Describes `meaning` of `name` in a sentence
+fn explain(name: &str, meaning: &Meaning) -> String {
+    match meaning {
+        Meaning::Alias(value) => format!("{} is aliased to {}", name, value::quote(value)),
+        Meaning::Keyword => format!("{} is a shell keyword", name),
+        Meaning::Command(ExecuteeKind::Function(_)) => format!("{} is a function", name),
+        Meaning::Command(ExecuteeKind::StrongBuiltin(_)) => format!("{} is a strong shell builtin", name),
+        Meaning::Command(ExecuteeKind::WeakBuiltin(_)) => format!("{} is a weak shell builtin", name),
+        Meaning::Command(ExecuteeKind::Binary(path)) => format!("{} is {}", name, path.display()),
+        Meaning::Command(ExecuteeKind::Compound(_)) => unreachable!(),
+    }
+}
+
+/// Tells what each name runs as a command
+///
+/// `-t` prints just a word for the kind, `-p` the path of a binary and
+/// `-a` lists everything the name could run instead of just what does.
+pub fn describe(args: &[String]) -> u8 {
+    let mut all = false;
+    let mut terse = false;
+    let mut path = false;
+    let mut names = Vec::new();
+    for arg in &args[1..] {
+        match arg.strip_prefix('-') {
+            Some(flags) if names.is_empty() && !flags.is_empty() => {
+                for flag in flags.chars() {
+                    match flag {
+                        'a' => all = true,
+                        't' => terse = true,
+                        'p' => path = true,
+                        _ => {
+                            error!("type: Invalid option -{}.", flag);
+                            return 2;
+                        },
+                    }
+                }
+            },
+            _ => names.push(arg),
+        }
     }
+
+    let mut retcode = 0;
+    for name in names {
+        let meanings = meanings(name, all);
+        if meanings.is_empty() {
+            if !terse && !path {
+                error!("type: {}: Not found.", name);
+            }
+            retcode = 1;
+        }
+        for meaning in &meanings {
+            let line = match (meaning, terse, path) {
+                (Meaning::Command(ExecuteeKind::Binary(bin)), _, true) => bin.display().to_string(),
+                (_, _, true) => continue,
+                (Meaning::Alias(_), true, _) => String::from("alias"),
+                (Meaning::Keyword, true, _) => String::from("keyword"),
+                (Meaning::Command(ExecuteeKind::Function(_)), true, _) => String::from("function"),
+                (Meaning::Command(ExecuteeKind::Binary(_)), true, _) => String::from("file"),
+                (Meaning::Command(_), true, _) => String::from("builtin"),
+                (meaning, false, false) => explain(name, meaning),
+            };
+            retcode = retcode.max(write_out("type", &format!("{}\n", line)));
+        }
+    }
+    retcode
+}
+
+/// Checks whether `name` can name an alias
+fn valid_alias(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || "/$`=|&;()<>'\"\\".contains(c))
+}
+
+/// Defines aliases given as `name=value` and prints those given by name,
+/// or all of them if there are no arguments or just `-p`
+pub fn alias(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let names = match &args[1..] {
+        [print, names @ ..] if print == "-p" => names,
+        names => names,
+    };
+    if names.is_empty() {
+        state_aliases(shell.aliases());
+        return 0;
+    }
+
+    let mut retcode = 0;
+    for arg in names {
+        match arg.split_once('=') {
+            Some((name, value)) if valid_alias(name) => {
+                shell.aliases_mut().insert(String::from(name), String::from(value));
+            },
+            Some((name, _)) => {
+                error!("alias: {}: Invalid alias name.", name);
+                retcode = 1;
+            },
+            None => match shell.aliases().get(arg) {
+                Some(value) => println!("alias {}={}", arg, value::quote(value)),
+                None => {
+                    error!("alias: {}: Not found.", arg);
+                    retcode = 1;
+                },
+            },
+        }
+    }
+    retcode
+}
+
+/// Removes the given aliases, or all of them with `-a`
+pub fn unalias(args: &[String]) -> u8 {
+    let shell = super::shell();
+    if args.get(1).is_some_and(|arg| arg == "-a") {
+        shell.aliases_mut().clear();
+        return 0;
+    }
+    if args.len() < 2 {
+        error!("unalias: Missing alias name.");
+        return 2;
+    }
+    let mut retcode = 0;
+    for name in &args[1..] {
+        if shell.aliases_mut().remove(name).is_none() {
+            error!("unalias: {}: Not found.", name);
+            retcode = 1;
+        }
+    }
+    retcode
+}
+
+/// Runs a command skipping functions, or with `-v` and `-V` tells what a
+/// name runs as a command, briefly or in a sentence
+///
+/// Running is done by the shell itself when it resolves the command, so
+/// here the command is only ever described.
+pub fn command(args: &[String]) -> u8 {
+    let mut verbose = None;
+    let mut names = &args[1..];
+    while let Some((first, rest)) = names.split_first() {
+        match first.as_str() {
+            "-v" => verbose = Some(false),
+            "-V" => verbose = Some(true),
+            "--" => {
+                names = rest;
+                break;
+            },
+            arg if arg.starts_with('-') => {
+                error!("command: Invalid option {}.", arg);
+                return 2;
+            },
+            _ => break,
+        }
+        names = rest;
+    }
+    let verbose = match verbose {
+        Some(verbose) => verbose,
+        None => return 0,
+    };
+
+    let mut retcode = 0;
+    for name in names {
+        let line = match meanings(name, false).first() {
+            Some(meaning) if verbose => explain(name, meaning),
+            Some(Meaning::Alias(value)) => format!("alias {}={}", name, value::quote(value)),
+            Some(Meaning::Command(ExecuteeKind::Binary(path))) => path.display().to_string(),
+            Some(_) => name.clone(),
+            None => {
+                if verbose {
+                    error!("command: {}: Not found.", name);
+                }
+                retcode = 1;
+                continue;
+            },
+        };
+        retcode = retcode.max(write_out("command", &format!("{}\n", line)));
+    }
+    retcode
 }
 
 pub fn state(args: &[String]) -> u8 {
-    let shell = unsafe { SHELL.as_ref().unwrap() };
+    let shell = super::shell();
     if args.len() == 2 {
         match args.get(1).unwrap().as_str() {
             "vars" => state_vars(shell.vars()),
             "bin" | "bin_dirs" => state_bin_dirs(shell.bin_dirs()),
             "config" => state_config(shell.config()),
-            _ => eprintln!("{}: Unknown option. ", args.get(0).unwrap()),
+            "dirs" => state_dirs(shell.dir_stack()),
+            "aliases" => state_aliases(shell.aliases()),
+            "fds" => state_fds(shell.fds()),
+            _ => error!("{}: Unknown option. ", args[0]),
         };
     } else {
         println!("VARS");
@@ -53,13 +1190,30 @@ pub fn state(args: &[String]) -> u8 {
         state_bin_dirs(shell.bin_dirs());
         println!("\nCONFIG");
         state_config(shell.config());
+        println!("\nDIRS");
+        state_dirs(shell.dir_stack());
+        println!("\nALIASES");
+        state_aliases(shell.aliases());
+        println!("\nFDS");
+        state_fds(shell.fds());
     }
     0
 }
 
-fn state_vars(vars: &HashMap<String, String>) {
-    for var in vars {
-        println!("{}={}", var.0, var.1);
+/// Prints variables sorted by name as assignments that read them back in
+fn state_vars(vars: &HashMap<String, Value>) {
+    let mut vars: Vec<_> = vars.iter().collect();
+    vars.sort_by_key(|var| var.0);
+    for (name, value) in vars {
+        print_var(name, value);
+    }
+}
+
+fn print_var(name: &str, value: &Value) {
+    if let Value::Associative(_) = value {
+        println!("declare -A {}; {}={}", name, name, value);
+    } else {
+        println!("{}={}", name, value);
     }
 }
 
@@ -70,12 +1224,306 @@ fn state_bin_dirs(bin_dirs: &Vec<String>) {
 }
 
 fn state_config(config: &Config) {
-    print!("{}", config.to_string());
+    print!("{}", config);
+}
+
+fn state_dirs(dir_stack: &Vec<String>) {
+    for dir in dir_stack {
+        println!("{}", dir);
+    }
+}
+
+/// Prints aliases sorted by name as commands that define them again
+fn state_aliases(aliases: &BTreeMap<String, String>) {
+    for (name, value) in aliases {
+        println!("alias {}={}", name, value::quote(value));
+    }
+}
+
+/// Prints descriptors opened by `exec` with what they refer to
+fn state_fds(fds: &BTreeSet<RawFd>) {
+    for fd in fds {
+        match fs::read_link(format!("/proc/self/fd/{}", fd)) {
+            Ok(target) => println!("{} {}", fd, target.to_string_lossy()),
+            Err(_) => println!("{}", fd),
+        }
+    }
 }
 
 pub fn fail(args: &[String]) -> u8 {
-    eprintln!("rush: Unknown command {}.", args[0]);
-    1
+    error!("rush: Unknown command {}.", args[0]);
+    let suggestions = suggest(&args[0]);
+    if !suggestions.is_empty() {
+        error!("rush: Did you mean {}?", suggestions.join(", "));
+    }
+    127
+}
+
+/// Returns builtins and binaries with names closest to `command`
+fn suggest(command: &str) -> Vec<String> {
+    let shell = super::shell();
+    let mut names: Vec<String> = BUILTINS.iter()
+        .map(|builtin| String::from(builtin.0))
+        .collect();
+    for dir in shell.bin_dirs() {
+        if let Ok(entries) = fs::read_dir(dir) {
+            names.extend(entries
+                .filter_map(Result::ok)
+                .filter_map(|entry| entry.file_name().into_string().ok()));
+        }
+    }
+    names.sort();
+    names.dedup();
+
+    let limit = (command.chars().count() / 3).clamp(1, 2);
+    let distances: Vec<_> = names.iter()
+        .map(|name| edit_distance(command, name))
+        .collect();
+    match distances.iter().copied().filter(|distance| *distance <= limit).min() {
+        Some(best) => names.into_iter()
+            .zip(distances)
+            .filter(|(_, distance)| *distance == best)
+            .map(|(name, _)| name)
+            .take(5)
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Computes the edit distance of `a` and `b`
+///
+/// Besides insertions, deletions and substitutions, swapping two adjacent
+/// characters counts as a single edit, as that is the most common typo.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Evaluates a conditional expression, returning 0 if it holds
+///
+/// Invoked as `[`, the last argument must be `]`.
+pub fn test(args: &[String]) -> u8 {
+    let name = args[0].as_str();
+    let mut operands = &args[1..];
+    if name == "[" {
+        match operands.split_last() {
+            Some((last, rest)) if last == "]" => operands = rest,
+            _ => {
+                error!("[: Missing ].");
+                return 2;
+            },
+        }
+    }
+    match condition::test(super::shell(), operands) {
+        Ok(result) => !result as u8,
+        Err(message) => {
+            error!("{}: {}", name, message);
+            2
+        },
+    }
+}
+
+/// Exits the shell with status `n`, by default that of the last pipeline,
+/// after running the `EXIT` trap
+///
+/// Commands run in the foreground until they exit, so there are never
+/// stopped jobs to warn about.
+pub fn exit(args: &[String]) -> u8 {
+    let shell = super::shell();
+    let status = match &args[1..] {
+        [] => shell.status() as u8,
+        [n] => match n.trim().parse::<i64>() {
+            Ok(n) => n as u8,
+            Err(_) => {
+                error!("exit: {}: Numeric argument required.", n);
+                2
+            },
+        },
+        _ => {
+            error!("exit: Too many arguments.");
+            return 1;
+        },
+    };
+    shell.exit(status);
+}
+
+/// Sets `action` to run on conditions, of which only `EXIT` (or `0`) is
+/// supported
+///
+/// An action of `-`, or none at all, resets the conditions. Without
+/// arguments, or with `-p`, prints the traps as commands that set them
+/// again.
+pub fn trap(args: &[String]) -> u8 {
+    let shell = super::shell();
+    if args.len() == 1 || args[1] == "-p" {
+        return match shell.exit_trap() {
+            Some(action) => write_out("trap", &format!("trap -- {} EXIT\n", value::quote(action))),
+            None => 0,
+        };
+    }
+    let args = match &args[1..] {
+        [dashes, rest @ ..] if dashes == "--" => rest,
+        rest => rest,
+    };
+    let (action, conditions) = match args {
+        [] => return 0,
+        [_] => (None, args),
+        [action, conditions @ ..] => (Some(action), conditions),
+    };
+    let action = action.filter(|action| *action != "-");
+    let mut retcode = 0;
+    for condition in conditions {
+        match condition.as_str() {
+            "EXIT" | "0" => shell.set_exit_trap(action.cloned()),
+            _ => {
+                error!("trap: {}: Unsupported condition.", condition);
+                retcode = 1;
+            },
+        }
+    }
+    retcode
+}
+
+/// Sets the file creation mask to `mode`, octal or symbolic as for
+/// `chmod`, or prints it
+///
+/// Symbolic modes, as printed with `-S`, tell the permissions left rather
+/// than those masked. `-p` prints the mask as a command that sets it again.
+pub fn umask(args: &[String]) -> u8 {
+    let mut symbolic = false;
+    let mut command = false;
+    let mut operands = args[1..].iter().peekable();
+    while let Some(flags) = operands.peek().and_then(|arg| arg.strip_prefix('-')).filter(|flags| !flags.is_empty()) {
+        if flags == "-" {
+            operands.next();
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'S' => symbolic = true,
+                'p' => command = true,
+                _ => {
+                    error!("umask: Invalid option -{}.", flag);
+                    return 2;
+                },
+            }
+        }
+        operands.next();
+    }
+
+    let mask = stat::umask(Mode::empty());
+    stat::umask(mask);
+    let mask = mask.bits() as u32;
+    let mode = match operands.next() {
+        Some(mode) => mode,
+        None => {
+            let text = if symbolic {
+                let allowed = !mask & 0o777;
+                let class = |shift: u32| ["r", "w", "x"].iter()
+                    .zip([4, 2, 1])
+                    .filter(|(_, bit)| allowed >> shift & bit != 0)
+                    .map(|(perm, _)| *perm)
+                    .collect::<String>();
+                format!("u={},g={},o={}", class(6), class(3), class(0))
+            } else {
+                format!("{:04o}", mask)
+            };
+            let line = match (command, symbolic) {
+                (true, true) => format!("umask -S {}\n", text),
+                (true, false) => format!("umask {}\n", text),
+                (false, _) => format!("{}\n", text),
+            };
+            return write_out("umask", &line);
+        },
+    };
+    let mask = if mode.starts_with(|c: char| c.is_ascii_digit()) {
+        u32::from_str_radix(mode, 8).ok().filter(|mask| *mask <= 0o777)
+    } else {
+        symbolic_mode(mode, !mask & 0o777).map(|allowed| !allowed & 0o777)
+    };
+    match mask {
+        Some(mask) => {
+            stat::umask(Mode::from_bits_truncate(mask as _));
+            0
+        },
+        None => {
+            error!("umask: {}: Invalid mode.", mode);
+            1
+        },
+    }
+}
+
+/// Applies symbolic mode `mode`, such as `u=rwx,go-w`, to permissions
+/// `perms`
+fn symbolic_mode(mode: &str, mut perms: u32) -> Option<u32> {
+    for clause in mode.split(',') {
+        let ops = clause.trim_start_matches(['u', 'g', 'o', 'a']);
+        let who = clause[..clause.len() - ops.len()].chars()
+            .map(|who| match who {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                _ => 0o777,
+            })
+            .fold(0, |who, class| who | class);
+        let who = if who == 0 { 0o777 } else { who };
+        let mut chars = ops.chars().peekable();
+        let mut op = chars.next()?;
+        loop {
+            let mut bits = 0;
+            while let Some(perm) = chars.next_if(|c| !matches!(c, '+' | '-' | '=')) {
+                bits |= match perm {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' | 'X' => 0o111,
+                    'u' => (perms >> 6 & 7) * 0o111,
+                    'g' => (perms >> 3 & 7) * 0o111,
+                    'o' => (perms & 7) * 0o111,
+                    's' | 't' => 0,
+                    _ => return None,
+                };
+            }
+            let bits = bits & who;
+            perms = match op {
+                '+' => perms | bits,
+                '-' => perms & !bits,
+                '=' => perms & !who | bits,
+                _ => return None,
+            };
+            match chars.next() {
+                Some(next) => op = next,
+                None => break,
+            }
+        }
+    }
+    Some(perms)
+}
+
+/// Stands in for `exec`, which the executor runs itself as it needs the
+/// redirections and environment of the command
+pub fn exec(_: &[String]) -> u8 {
+    0
+}
+
+pub fn colon(_: &[String]) -> u8 {
+    0
 }
 
 pub fn void() -> u8 {
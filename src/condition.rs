@@ -0,0 +1,207 @@
+use std::fs::{self, Metadata};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+use nix::unistd::{self, AccessFlags};
+
+use super::shell::{Shell, OPTIONS};
+
+/// Checks whether `op` is a unary operator of `test`
+pub fn is_unary(op: &str) -> bool {
+    matches!(op,
+        "-a" | "-b" | "-c" | "-d" | "-e" | "-f" | "-g" | "-h" | "-k" | "-n" | "-o" | "-p" | "-r" |
+        "-s" | "-t" | "-u" | "-v" | "-w" | "-x" | "-z" | "-G" | "-L" | "-N" | "-O" | "-R" | "-S"
+    )
+}
+
+/// Checks whether `op` is a binary operator of `test`
+pub fn is_binary(op: &str) -> bool {
+    matches!(op,
+        "=" | "==" | "!=" | "<" | ">" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" | "-nt" | "-ot" | "-ef"
+    )
+}
+
+/// Evaluates unary operator `op` on `operand`
+///
+/// Most operators test a file, such as `-d` whether it is a directory.
+/// The others test a string, `-z` and `-n` whether it is empty, `-v`
+/// whether it names a set variable, `-o` whether it names an option that
+/// is on and `-t` whether it is a file descriptor open on a terminal.
+pub fn unary(shell: &Shell, op: &str, operand: &str) -> bool {
+    let mode = |mask| metadata(operand).is_some_and(|meta| meta.mode() & mask != 0);
+    let access = |flags| !operand.is_empty() && unistd::access(operand, flags).is_ok();
+    match op {
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        "-v" => shell.value(operand).is_some(),
+        "-o" => OPTIONS.iter().any(|(name, flag)| *name == operand && shell.flag(*flag)),
+        // Name references do not exist.
+        "-R" => false,
+        "-t" => operand.trim().parse().is_ok_and(|fd| unistd::isatty(fd).unwrap_or(false)),
+        "-a" | "-e" => metadata(operand).is_some(),
+        "-h" | "-L" => fs::symlink_metadata(operand).is_ok_and(|meta| meta.file_type().is_symlink()),
+        "-b" => metadata(operand).is_some_and(|meta| meta.file_type().is_block_device()),
+        "-c" => metadata(operand).is_some_and(|meta| meta.file_type().is_char_device()),
+        "-d" => metadata(operand).is_some_and(|meta| meta.is_dir()),
+        "-f" => metadata(operand).is_some_and(|meta| meta.is_file()),
+        "-p" => metadata(operand).is_some_and(|meta| meta.file_type().is_fifo()),
+        "-S" => metadata(operand).is_some_and(|meta| meta.file_type().is_socket()),
+        "-s" => metadata(operand).is_some_and(|meta| meta.len() > 0),
+        "-u" => mode(0o4000),
+        "-g" => mode(0o2000),
+        "-k" => mode(0o1000),
+        "-r" => access(AccessFlags::R_OK),
+        "-w" => access(AccessFlags::W_OK),
+        "-x" => access(AccessFlags::X_OK),
+        "-O" => metadata(operand).is_some_and(|meta| meta.uid() == unistd::geteuid().as_raw()),
+        "-G" => metadata(operand).is_some_and(|meta| meta.gid() == unistd::getegid().as_raw()),
+        "-N" => metadata(operand).is_some_and(|meta| (meta.mtime(), meta.mtime_nsec()) > (meta.atime(), meta.atime_nsec())),
+        _ => false,
+    }
+}
+
+/// Evaluates binary operator `op` on `lhs` and `rhs`
+///
+/// Strings compare by their bytes, integers by value and files by their
+/// modification time with `-nt` and `-ot`, or by identity with `-ef`.
+/// Fails if an integer operand is not an integer.
+pub fn binary(lhs: &str, op: &str, rhs: &str) -> Result<bool, String> {
+    let result = match op {
+        "=" | "==" => lhs == rhs,
+        "!=" => lhs != rhs,
+        "<" => lhs < rhs,
+        ">" => lhs > rhs,
+        "-eq" => integer(lhs)? == integer(rhs)?,
+        "-ne" => integer(lhs)? != integer(rhs)?,
+        "-lt" => integer(lhs)? < integer(rhs)?,
+        "-le" => integer(lhs)? <= integer(rhs)?,
+        "-gt" => integer(lhs)? > integer(rhs)?,
+        "-ge" => integer(lhs)? >= integer(rhs)?,
+        "-nt" => match (modified(lhs), modified(rhs)) {
+            (Some(lhs), Some(rhs)) => lhs > rhs,
+            (lhs, _) => lhs.is_some(),
+        },
+        "-ot" => match (modified(lhs), modified(rhs)) {
+            (Some(lhs), Some(rhs)) => lhs < rhs,
+            (_, rhs) => rhs.is_some(),
+        },
+        "-ef" => match (metadata(lhs), metadata(rhs)) {
+            (Some(lhs), Some(rhs)) => (lhs.dev(), lhs.ino()) == (rhs.dev(), rhs.ino()),
+            _ => false,
+        },
+        _ => return Err(format!("{}: Unknown operator.", op)),
+    };
+    Ok(result)
+}
+
+fn integer(text: &str) -> Result<i64, String> {
+    text.trim().parse().map_err(|_| format!("{}: Integer expression expected.", text))
+}
+
+fn metadata(path: &str) -> Option<Metadata> {
+    fs::metadata(path).ok()
+}
+
+fn modified(path: &str) -> Option<(i64, i64)> {
+    metadata(path).map(|meta| (meta.mtime(), meta.mtime_nsec()))
+}
+
+/// Evaluates the arguments of `test`, without the closing `]` of `[`
+///
+/// Up to four arguments are told apart by their number as POSIX requires,
+/// so that `test -n` and `test ! = x` mean what they say. Longer
+/// expressions are parsed with `!`, `-a`, `-o` and parentheses, in order
+/// of decreasing precedence.
+pub fn test(shell: &Shell, args: &[String]) -> Result<bool, String> {
+    let arg = |n: usize| args[n].as_str();
+    match args.len() {
+        0 => Ok(false),
+        1 => Ok(!arg(0).is_empty()),
+        2 if arg(0) == "!" => Ok(!test(shell, &args[1..])?),
+        2 if is_unary(arg(0)) => Ok(unary(shell, arg(0), arg(1))),
+        2 => Err(format!("{}: Unary operator expected.", arg(0))),
+        3 if is_binary(arg(1)) => binary(arg(0), arg(1), arg(2)),
+        3 if arg(1) == "-a" => Ok(!arg(0).is_empty() && !arg(2).is_empty()),
+        3 if arg(1) == "-o" => Ok(!arg(0).is_empty() || !arg(2).is_empty()),
+        3 if arg(0) == "!" => Ok(!test(shell, &args[1..])?),
+        3 if arg(0) == "(" && arg(2) == ")" => test(shell, &args[1..2]),
+        3 => Err(format!("{}: Binary operator expected.", arg(1))),
+        4 if arg(0) == "!" => Ok(!test(shell, &args[1..])?),
+        4 if arg(0) == "(" && arg(3) == ")" => test(shell, &args[1..3]),
+        _ => {
+            let mut parser = Parser { shell, args, pos: 0 };
+            let result = parser.or()?;
+            match args.get(parser.pos) {
+                Some(arg) => Err(format!("{}: Unexpected argument.", arg)),
+                None => Ok(result),
+            }
+        },
+    }
+}
+
+/// Recursive descent parser of long `test` expressions
+struct Parser<'a> {
+    shell: &'a Shell,
+    args: &'a [String],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.pos + offset).map(String::as_str)
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            result = self.and()? || result;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            result = self.not()? && result;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        if self.peek(0) == Some("!") {
+            self.pos += 1;
+            return Ok(!self.not()?);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        match (self.peek(0), self.peek(1), self.peek(2)) {
+            (Some(lhs), Some(op), Some(rhs)) if is_binary(op) => {
+                self.pos += 3;
+                binary(lhs, op, rhs)
+            },
+            (Some("("), _, _) => {
+                self.pos += 1;
+                let result = self.or()?;
+                match self.peek(0) {
+                    Some(")") => {
+                        self.pos += 1;
+                        Ok(result)
+                    },
+                    _ => Err(String::from("Missing ).")),
+                }
+            },
+            (Some(op), Some(operand), _) if is_unary(op) => {
+                self.pos += 2;
+                Ok(unary(self.shell, op, operand))
+            },
+            (Some(word), _, _) => {
+                self.pos += 1;
+                Ok(!word.is_empty())
+            },
+            (None, _, _) => Err(String::from("Argument expected.")),
+        }
+    }
+}
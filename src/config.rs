@@ -1,15 +1,24 @@
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
 use std::path::Path;
 
 use serde::{Serialize, Deserialize};
-use toml;
 
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub respect_vars: bool,
     pub respect_path: bool,
     pub bin_dirs: Vec<String>,
     pub prompt: String,
+    pub continuation_prompt: String,
+    pub auto_pushd: bool,
+    pub frecency: bool,
+    pub auto_cd: bool,
+    pub command_not_found: Option<String>,
+    pub interactive_comments: bool,
+    pub aliases: BTreeMap<String, String>,
 }
 
 impl Config {
@@ -21,9 +30,11 @@ impl Config {
         }
         None
     }
+}
 
-    pub fn to_string(&self) -> String {
-        toml::to_string(&self).unwrap()
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", toml::to_string(&self).unwrap())
     }
 }
 
@@ -34,6 +45,13 @@ impl Default for Config {
             respect_path: true,
             bin_dirs: vec![String::from("/bin"), String::from("/usr/bin")],
             prompt: String::from("» "),
+            continuation_prompt: String::from("> "),
+            auto_pushd: false,
+            frecency: true,
+            auto_cd: false,
+            command_not_found: None,
+            interactive_comments: true,
+            aliases: BTreeMap::new(),
         }
     }
 }
\ No newline at end of file
@@ -1,13 +1,17 @@
 use std::collections::HashMap;
+use std::fs;
 use std::path::PathBuf;
 use std::process;
 use std::ffi::CString;
+use std::io::Write;
 use std::os::unix::io::RawFd;
 
 use super::builtin;
+use super::parser::Command;
 
+use nix::errno::Errno;
 use nix::sys::stat::Mode;
-use nix::fcntl::{self, OFlag};
+use nix::fcntl::{self, FcntlArg, FdFlag, OFlag};
 use nix::unistd::{self, fork, ForkResult};
 use nix::sys::wait::{self, WaitStatus};
 use nix::sys::signal::{self, Signal, SigHandler};
@@ -16,14 +20,18 @@ use nix::sys::signal::{self, Signal, SigHandler};
 pub enum ExecuteeKind {
     StrongBuiltin(String),
     WeakBuiltin(String),
+    Function(String),
+    Compound(Box<Command<'static>>),
     Binary(PathBuf),
 }
 
 pub enum RedirectKind {
     Dup(RawFd),
     Mov(RawFd),
+    Close,
     Read(String),
     Write(String),
+    Create(String),
     Append(String),
     RW(String),
 }
@@ -49,10 +57,23 @@ impl Executee {
         self.kind = kind;
     }
 
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
     pub fn arg(&mut self, word: String) {
         self.args.push(word);
     }
 
+    pub fn var(&mut self, name: String, value: String) {
+        self.vars.insert(name, value);
+    }
+
+    /// Returns the value of `name` in the environment of the executee
+    pub fn env_var(&self, name: &str) -> Option<&String> {
+        self.vars.get(name)
+    }
+
     fn redirect(&mut self, src: RedirectKind, dst: RawFd) {
         self.redirect.push((src, dst));
     }
@@ -65,10 +86,24 @@ impl Executee {
         self.redirect(RedirectKind::Mov(src), dst);
     }
 
+    /// Connects `dst` to pipe end `src` ahead of the redirections of the
+    /// executee, which then apply to the pipe
+    pub fn pipe(&mut self, src: RawFd, dst: RawFd) {
+        self.redirect.insert(0, (RedirectKind::Mov(src), dst));
+    }
+
+    pub fn fd_close(&mut self, fd: RawFd) {
+        self.redirect(RedirectKind::Close, fd);
+    }
+
     pub fn file_write(&mut self, file: String, fd: RawFd) {
         self.redirect(RedirectKind::Write(file), fd);
     }
 
+    pub fn file_create(&mut self, file: String, fd: RawFd) {
+        self.redirect(RedirectKind::Create(file), fd);
+    }
+
     pub fn file_read(&mut self, file: String, fd: RawFd) {
         self.redirect(RedirectKind::Read(file), fd);
     }
@@ -81,6 +116,10 @@ impl Executee {
         self.redirect(RedirectKind::RW(file), fd);
     }
 
+    pub fn kind(&self) -> &ExecuteeKind {
+        &self.kind
+    }
+
     pub fn args(&self) -> &Vec<String> {
         &self.args
     }
@@ -100,101 +139,214 @@ impl Executee {
     }
 }
 
+/// Applies `redirect` to the current process
+///
+/// On failure, returns the message to report.
+fn apply(redirect: &(RedirectKind, RawFd)) -> Result<(), String> {
+    let dst = redirect.1;
+    let (file, oflag) = match &redirect.0 {
+        RedirectKind::Dup(src) => return duplicate(*src, dst, false),
+        RedirectKind::Mov(src) => return duplicate(*src, dst, true),
+        RedirectKind::Close => {
+            // Closing a descriptor that is not open is no error.
+            let _ = unistd::close(dst);
+            return Ok(());
+        },
+        RedirectKind::Read(file) => (file, OFlag::O_RDONLY),
+        RedirectKind::Write(file) => (file, OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC),
+        // Only regular files are protected, so that `/dev/null` still works.
+        RedirectKind::Create(file) => match fs::metadata(file) {
+            Ok(meta) if meta.is_file() => return Err(format!("rush: {}: Cannot overwrite existing file.", file)),
+            Ok(_) => (file, OFlag::O_WRONLY),
+            Err(_) => (file, OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL),
+        },
+        RedirectKind::Append(file) => (file, OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_APPEND),
+        RedirectKind::RW(file) => (file, OFlag::O_RDWR | OFlag::O_CREAT),
+    };
+    // The umask of the process takes away from these permissions.
+    let mode = Mode::from_bits_truncate(0o666);
+    let src = fcntl::open(file.as_str(), oflag, mode).map_err(|error| open_error(file, error))?;
+    duplicate(src, dst, true)
+}
+
+/// Makes `dst` refer to what `src` does, closing `src` if `close_src`
+fn duplicate(src: RawFd, dst: RawFd, close_src: bool) -> Result<(), String> {
+    if src == dst {
+        // Redirecting a descriptor to itself asks for it to be inherited.
+        fcntl::fcntl(dst, FcntlArg::F_SETFD(FdFlag::empty())).map_err(|error| format!("rush: {}: {}.", dst, error.desc()))?;
+        return Ok(());
+    }
+    unistd::dup2(src, dst).map_err(|error| format!("rush: {}: {}.", src, error.desc()))?;
+    if close_src {
+        unistd::close(src).unwrap();
+    }
+    Ok(())
+}
+
+fn open_error(file: &str, error: Errno) -> String {
+    format!("rush: {}: {}.", file, error.desc())
+}
+
 fn execute(executee: &Executee) -> ! {
     unsafe { signal::signal(Signal::SIGINT, SigHandler::SigDfl) }.unwrap();
+    // Traps are not inherited by subshells.
+    super::shell().set_exit_trap(None);
     for redirect in &executee.redirect {
-        let mut close_src = false;
-        let src = match &redirect.0 {
-            RedirectKind::Dup(fd) => *fd,
-            RedirectKind::Mov(fd) => {
-                close_src = true;
-                *fd
-            },
-            RedirectKind::Write(file) => {
-                let mut oflag = OFlag::empty();
-                oflag.insert(OFlag::O_WRONLY);
-                oflag.insert(OFlag::O_CREAT);
-                oflag.insert(OFlag::O_TRUNC);
-                let mut mode = Mode::empty();
-                mode.insert(Mode::S_IWUSR);
-                mode.insert(Mode::S_IRUSR);
-                mode.insert(Mode::S_IRGRP);
-                mode.insert(Mode::S_IROTH);
-                fcntl::open(file.as_str(), oflag, mode).unwrap_or_else(|_| {
-                    eprintln!("rush: Could not open file {}.", file);
-                    process::exit(1 as i32);
-                })
-            },
-            RedirectKind::Read(file) => {
-                let mut oflag = OFlag::empty();
-                oflag.insert(OFlag::O_RDONLY);
-                let mode = Mode::empty();
-                fcntl::open(file.as_str(), oflag, mode).unwrap_or_else(|_| {
-                    eprintln!("rush: Could not open file {}.", file);
-                    process::exit(1 as i32);
-                })
-            },
-            RedirectKind::Append(file) => {
-                let mut oflag = OFlag::empty();
-                oflag.insert(OFlag::O_WRONLY);
-                oflag.insert(OFlag::O_CREAT);
-                oflag.insert(OFlag::O_APPEND);
-                let mut mode = Mode::empty();
-                mode.insert(Mode::S_IWUSR);
-                mode.insert(Mode::S_IRUSR);
-                mode.insert(Mode::S_IRGRP);
-                mode.insert(Mode::S_IROTH);
-                fcntl::open(file.as_str(), oflag, mode).unwrap_or_else(|_| {
-                    eprintln!("rush: Could not open file {}.", file);
-                    process::exit(1 as i32);
-                })
-            },
-            RedirectKind::RW(file) => {
-                let mut oflag = OFlag::empty();
-                oflag.insert(OFlag::O_RDWR);
-                oflag.insert(OFlag::O_CREAT);
-                oflag.insert(OFlag::O_TRUNC);
-                let mut mode = Mode::empty();
-                mode.insert(Mode::S_IWUSR);
-                mode.insert(Mode::S_IRUSR);
-                mode.insert(Mode::S_IRGRP);
-                mode.insert(Mode::S_IROTH);
-                fcntl::open(file.as_str(), oflag, mode).unwrap_or_else(|_| {
-                    eprintln!("rush: Could not open file {}.", file);
-                    process::exit(1 as i32);
-                })
-            },
-        };
-        let dst = redirect.1;
-        unistd::dup2(src, dst).unwrap();
-        if close_src {
-            unistd::close(src).unwrap();
+        if let Err(message) = apply(redirect) {
+            error!("{}", message);
+            process::exit(1);
         }
     }
     match executee.kind.clone() {
-        ExecuteeKind::WeakBuiltin(name) => {
-            let retcode = match name.as_str() {
-                "state" => builtin::state(&executee.args),
-                "fail" => builtin::fail(&executee.args),
-                "void" => builtin::void(),
-                _ => 1,
-            };
-            process::exit(retcode as i32);
+        ExecuteeKind::StrongBuiltin(name) if name == "exec" => {
+            process::exit(replace(executee) as i32);
+        },
+        ExecuteeKind::StrongBuiltin(name) | ExecuteeKind::WeakBuiltin(name) => {
+            process::exit(builtin::run(&name, &executee.args) as i32);
         }
+        ExecuteeKind::Function(name) => {
+            let shell = super::shell();
+            let retcode = shell.call_function(&name, &executee.args);
+            shell.exit(retcode as u8);
+        },
+        ExecuteeKind::Compound(command) => {
+            let shell = super::shell();
+            let retcode = shell.run_forked(&command);
+            shell.exit(retcode as u8);
+        },
         ExecuteeKind::Binary(bin) => {
             unistd::execve(&CString::new(bin.to_str().unwrap()).unwrap(), &executee.cargs(), &executee.cvars()).unwrap();
         },
-        _ => process::exit(1)
     };
     panic!("Child did not exec!");
 }
 
-pub fn execute_single(executee: &Executee) -> u8 {
-    if let ExecuteeKind::StrongBuiltin(name) = executee.kind.clone() {
-        match name.as_str() {
-            "cd" => builtin::cd(&executee.args),
-            _ => 1
+/// Runs `f` in the shell process with the redirections of `executee`
+/// applied, undoing them afterwards
+pub fn in_process<F: FnOnce() -> u8>(executee: &Executee, f: F) -> u8 {
+    std::io::stdout().flush().unwrap_or(());
+    let mut saved = Vec::new();
+    let mut retcode = None;
+    for redirect in &executee.redirect {
+        // A move closes its source, which needs restoring as well.
+        let moved = match redirect.0 {
+            RedirectKind::Mov(src) => Some(src),
+            _ => None,
+        };
+        for fd in std::iter::once(redirect.1).chain(moved) {
+            if !saved.iter().any(|(saved_fd, _, _)| *saved_fd == fd) {
+                let flags = fcntl::fcntl(fd, FcntlArg::F_GETFD).ok();
+                saved.push((fd, fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(10)).ok(), flags));
+            }
+        }
+        if let Err(message) = apply(redirect) {
+            error!("{}", message);
+            retcode = Some(1);
+            break;
+        }
+    }
+
+    let retcode = retcode.unwrap_or_else(f);
+
+    std::io::stdout().flush().unwrap_or(());
+    for (fd, copy, flags) in saved.into_iter().rev() {
+        match copy {
+            Some(copy) => {
+                unistd::dup2(copy, fd).unwrap();
+                unistd::close(copy).unwrap();
+                // `dup2` leaves the descriptor inheritable whatever it was.
+                if let Some(flags) = flags {
+                    fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::from_bits_truncate(flags))).unwrap();
+                }
+            },
+            None => {
+                let _ = unistd::close(fd);
+            },
         }
+    }
+    retcode
+}
+
+/// Runs `exec` in the shell process
+///
+/// With a command, the shell process is replaced by it. Without one, the
+/// redirections of `executee` stay in place for good. Descriptors above 2
+/// opened that way are tracked by the shell and made close-on-exec, so that
+/// only commands redirecting them inherit them.
+pub fn exec(executee: &Executee) -> u8 {
+    std::io::stdout().flush().unwrap_or(());
+    let fds = super::shell().fds_mut();
+    for redirect in &executee.redirect {
+        if let Err(message) = apply(redirect) {
+            error!("{}", message);
+            return 1;
+        }
+        let dst = redirect.1;
+        match redirect.0 {
+            RedirectKind::Mov(src) => {
+                fds.remove(&src);
+            },
+            RedirectKind::Close => {
+                fds.remove(&dst);
+                continue;
+            },
+            _ => (),
+        }
+        if dst > 2 {
+            fcntl::fcntl(dst, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).unwrap();
+            fds.insert(dst);
+        }
+    }
+    replace(executee)
+}
+
+/// Replaces the process with the binary named by the arguments of `exec`,
+/// if there are any
+///
+/// Returns only if that fails, which also ends a non-interactive shell.
+fn replace(executee: &Executee) -> u8 {
+    let args = match &executee.args[1..] {
+        [dashes, rest @ ..] if dashes == "--" => rest,
+        rest => rest,
+    };
+    let name = match args.first() {
+        Some(name) => name,
+        None => return 0,
+    };
+    let shell = super::shell();
+    let path = if name.contains('/') {
+        Some(PathBuf::from(name))
+    } else {
+        shell.candidates(name).find_map(|kind| match kind {
+            ExecuteeKind::Binary(path) => Some(path),
+            _ => None,
+        })
+    };
+    match path {
+        Some(path) => {
+            let handler = unsafe { signal::signal(Signal::SIGINT, SigHandler::SigDfl) }.unwrap();
+            let args: Vec<_> = args.iter()
+                .map(|arg| CString::new(arg.as_str()).unwrap())
+                .collect();
+            let error = unistd::execve(&CString::new(path.to_str().unwrap()).unwrap(), &args, &executee.cvars()).unwrap_err();
+            unsafe { signal::signal(Signal::SIGINT, handler) }.unwrap();
+            error!("exec: {}: {}.", name, error.desc());
+        },
+        None => error!("exec: {}: Not found.", name),
+    }
+    if !shell.interactive() {
+        shell.exit(127);
+    }
+    127
+}
+
+pub fn execute_single(executee: &Executee) -> u8 {
+    if matches!(&executee.kind, ExecuteeKind::StrongBuiltin(name) if name == "exec") {
+        exec(executee)
+    } else if let ExecuteeKind::StrongBuiltin(name) = executee.kind.clone() {
+        in_process(executee, || builtin::run(&name, &executee.args))
+    } else if let ExecuteeKind::Function(name) = executee.kind.clone() {
+        in_process(executee, || super::shell().call_function(&name, &executee.args) as u8)
     } else {
         match unsafe { unistd::fork() } {
             Ok(ForkResult::Parent { child, .. }) => {
@@ -231,13 +383,16 @@ pub fn execute_group(executees: &mut [Executee]) -> u8 {
             unsafe { signal::signal(Signal::SIGINT, SigHandler::SigDfl) }.unwrap();
             
             for i in 0..(executees.len() - 1) {
-                let pipe = unistd::pipe().unwrap();
-                executees[i].fd_duplicate(pipe.1, 1);
+                let pipe = unistd::pipe2(OFlag::O_CLOEXEC).unwrap();
+                executees[i].pipe(pipe.1, 1);
                 if let Ok(ForkResult::Child) = unsafe { fork() } {
                     execute(&executees[i]);
                 }
                 unistd::close(pipe.1).unwrap();
+                // Standard input is connected before the next command gets
+                // to apply its own redirections.
                 unistd::dup2(pipe.0, 0).unwrap();
+                unistd::close(pipe.0).unwrap();
             }
 
             let last = executees.last().unwrap();
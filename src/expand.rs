@@ -0,0 +1,682 @@
+use super::glob;
+use super::shell::Shell;
+use super::value;
+
+/// Field separators used when `IFS` is unset
+const DEFAULT_IFS: &str = " \t\n";
+
+/// Returns the field separators of `shell`
+pub fn ifs(shell: &Shell) -> String {
+    shell.param("IFS").unwrap_or_else(|| String::from(DEFAULT_IFS))
+}
+
+/// Checks whether `c` is IFS whitespace, which separates fields in runs
+/// rather than one by one
+fn ifs_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n')
+}
+
+/// Fields produced by expanding a word
+struct Fields {
+    done: Vec<String>,
+    current: String,
+    /// Whether `current` is a field even when empty, as after `""`
+    keep: bool,
+    /// Separators to split unquoted expansions on, `None` if they are not
+    /// split at all
+    ifs: Option<String>,
+    /// Characters escaped by a backslash when quoted, so that they match
+    /// literally in a pattern
+    special: &'static str,
+    /// Whether IFS whitespace just ended a field, which absorbs a following
+    /// non-whitespace separator
+    delimited: bool,
+}
+
+impl Fields {
+    fn new(ifs: Option<String>, special: &'static str) -> Fields {
+        Fields { done: Vec::new(), current: String::new(), keep: false, ifs, special, delimited: false }
+    }
+
+    fn push(&mut self, c: char) {
+        self.current.push(c);
+        self.keep = true;
+        self.delimited = false;
+    }
+
+    /// Appends quoted character `c`
+    fn push_quoted(&mut self, c: char) {
+        if self.special.contains(c) {
+            self.current.push('\\');
+        }
+        self.push(c);
+    }
+
+    fn push_str_quoted(&mut self, s: &str) {
+        for c in s.chars() {
+            self.push_quoted(c);
+        }
+        self.keep = true;
+    }
+
+    /// Ends the current field and starts a new one
+    fn split(&mut self) {
+        self.done.push(std::mem::take(&mut self.current));
+        self.keep = false;
+    }
+
+    /// Appends the result of an unquoted expansion, splitting it on IFS
+    ///
+    /// Runs of IFS whitespace separate fields and are dropped at the ends.
+    /// Any other IFS character ends a field by itself, together with the
+    /// whitespace around it, so two in a row delimit an empty field.
+    fn push_split(&mut self, value: &str) {
+        let ifs = match &self.ifs {
+            Some(ifs) => ifs.clone(),
+            None => {
+                self.current.push_str(value);
+                return;
+            },
+        };
+        for c in value.chars() {
+            self.push_separated(c, &ifs);
+        }
+    }
+
+    /// Appends `c`, which ends the current field if it is in `ifs`
+    fn push_separated(&mut self, c: char, ifs: &str) {
+        if !ifs.contains(c) {
+            self.push(c);
+        } else if ifs_whitespace(c) {
+            if self.keep || !self.current.is_empty() {
+                self.split();
+                self.delimited = true;
+            }
+        } else {
+            if !self.delimited {
+                self.split();
+            }
+            self.delimited = false;
+        }
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        if self.keep || !self.current.is_empty() {
+            self.done.push(self.current);
+        }
+        self.done
+    }
+}
+
+/// Splits `line` into at most `count` fields on `ifs`, as `read` does
+///
+/// The last field takes the rest of the line without the IFS whitespace
+/// around it, and without a trailing separator if that is all that
+/// separates anything in it. Unless `raw`, a backslash makes the next
+/// character literal.
+pub fn split_line(line: &str, ifs: &str, count: usize, raw: bool) -> Vec<String> {
+    let mut fields = Fields::new(Some(String::from(ifs)), "");
+    let separator = |c: char, escaped: bool| !escaped && ifs.contains(c);
+    let mut rest = false;
+    // Byte offsets of unescaped separators in the rest and where the rest
+    // ends if trailing IFS whitespace is dropped
+    let mut separators = Vec::new();
+    let mut end = 0;
+    let mut chars = line.chars();
+    while let Some(mut c) = chars.next() {
+        let escaped = !raw && c == '\\';
+        if escaped {
+            match chars.next() {
+                Some(next) => c = next,
+                None => break,
+            }
+        }
+        if !rest && fields.done.len() + 1 >= count && !fields.keep {
+            rest = !separator(c, escaped) || !(ifs_whitespace(c) || fields.delimited);
+        }
+        if !rest {
+            if escaped {
+                fields.push(c);
+            } else {
+                fields.push_separated(c, ifs);
+            }
+            continue;
+        }
+        if separator(c, escaped) {
+            separators.push((fields.current.len(), c));
+        }
+        fields.push(c);
+        if !separator(c, escaped) || !ifs_whitespace(c) {
+            end = fields.current.len();
+        }
+    }
+
+    if rest {
+        fields.current.truncate(end);
+        separators.retain(|(offset, _)| *offset < end);
+        if let Some(&(last, c)) = separators.last() {
+            let mut start = last;
+            for &(offset, c) in separators.iter().rev().skip(1) {
+                if offset + 1 != start || !ifs_whitespace(c) {
+                    break;
+                }
+                start = offset;
+            }
+            let alone = separators.iter().all(|(offset, _)| *offset >= start);
+            if !ifs_whitespace(c) && last + c.len_utf8() == end && alone {
+                fields.current.truncate(start);
+            }
+        }
+    }
+    fields.finish()
+}
+
+/// Expands parameters in `word`, splits the results of unquoted expansions
+/// on `IFS` and removes quotes
+///
+/// Returns the resulting fields. There may be more than one when an
+/// expansion splits or `"$@"` expands to several positional parameters, or
+/// none when an unquoted expansion is empty.
+pub fn word(shell: &Shell, word: &str) -> Vec<String> {
+    expand(shell, word, Some(ifs(shell)), "")
+}
+
+/// Expands `word` into a single string without splitting it, as is done
+/// for assignments and redirection targets
+pub fn string(shell: &Shell, word: &str) -> String {
+    expand(shell, word, None, "").join(" ")
+}
+
+/// Expands `word` into a pattern for the glob matcher, in which quoted
+/// parts match literally
+pub fn pattern(shell: &Shell, word: &str) -> String {
+    expand(shell, word, None, "*?[]\\").join(" ")
+}
+
+/// Expands `word` into a regular expression, in which quoted parts match
+/// literally
+pub fn regex(shell: &Shell, word: &str) -> String {
+    expand(shell, word, None, "\\.+*?()|[]{}^$").join(" ")
+}
+
+fn expand(shell: &Shell, word: &str, ifs: Option<String>, special: &'static str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut fields = Fields::new(ifs, special);
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\'' => {
+                fields.keep = true;
+                i += 1;
+                while i < chars.len() && chars[i] != '\'' {
+                    fields.push_quoted(chars[i]);
+                    i += 1;
+                }
+                i += 1;
+            },
+            '"' => {
+                // A quoted "$@" on its own must not leave an empty field
+                // behind when there are no positional parameters, though
+                // an empty "" is a field.
+                let mut only_at = None;
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    match chars[i] {
+                        '\\' if matches!(chars.get(i + 1), Some('$' | '`' | '"' | '\\')) => {
+                            fields.push_quoted(chars[i + 1]);
+                            only_at = Some(false);
+                            i += 2;
+                        },
+                        '\\' if chars.get(i + 1) == Some(&'\n') => i += 2,
+                        '$' => {
+                            let (next, at) = parameter(shell, &chars, i, true, &mut fields);
+                            only_at = Some(only_at.unwrap_or(true) && at);
+                            i = next;
+                        },
+                        c => {
+                            fields.push_quoted(c);
+                            only_at = Some(false);
+                            i += 1;
+                        },
+                    }
+                }
+                if only_at != Some(true) {
+                    fields.keep = true;
+                }
+                i += 1;
+            },
+            '\\' => {
+                if let Some(c) = chars.get(i + 1).filter(|c| **c != '\n') {
+                    fields.push_quoted(*c);
+                }
+                i += 2;
+            },
+            '$' => i = parameter(shell, &chars, i, false, &mut fields).0,
+            c => {
+                fields.push(c);
+                i += 1;
+            },
+        }
+    }
+    fields.finish()
+}
+
+/// Result of looking up a parameter
+enum Lookup {
+    /// Single value, `None` if unset
+    Scalar(Option<String>),
+    /// Values of `$@` or of all elements of an array, which a quoted
+    /// expansion joins into one field if `joined`, as with `$*`
+    List(Vec<String>, bool),
+}
+
+/// Expands the parameter starting with `$` at `chars[start]` into `fields`
+///
+/// Returns the index following the expansion and whether it was a quoted
+/// list of separate fields, like `"$@"`. A `$` not followed by a parameter
+/// name stands for itself. Unless `quoted`, the value is split into fields.
+fn parameter(shell: &Shell, chars: &[char], start: usize, quoted: bool, fields: &mut Fields) -> (usize, bool) {
+    let mut i = start + 1;
+    let lookup = match chars.get(i) {
+        Some('{') => {
+            let mut depth = 0;
+            let begin = i + 1;
+            while i < chars.len() {
+                match chars[i] {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    },
+                    _ => (),
+                }
+                i += 1;
+            }
+            let expression: String = chars[begin..i.min(chars.len())].iter().collect();
+            i += 1;
+            braced(shell, &expression)
+        },
+        Some(c) if c.is_ascii_digit() || "#*@?$!-".contains(*c) => {
+            i += 1;
+            simple(shell, &c.to_string())
+        },
+        Some(c) if c.is_alphabetic() || *c == '_' => {
+            let begin = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            simple(shell, &chars[begin..i].iter().collect::<String>())
+        },
+        _ => {
+            fields.push('$');
+            return (i, false);
+        },
+    };
+
+    match lookup {
+        Lookup::List(items, false) if quoted => {
+            for (n, item) in items.iter().enumerate() {
+                if n > 0 {
+                    fields.split();
+                }
+                fields.push_str_quoted(item);
+            }
+            return (i, true);
+        },
+        Lookup::List(items, true) if quoted => {
+            let separator: String = ifs(shell).chars().take(1).collect();
+            fields.push_str_quoted(&items.join(&separator));
+        },
+        Lookup::List(items, _) => {
+            for (n, item) in items.iter().enumerate() {
+                if n > 0 && fields.ifs.is_none() {
+                    fields.current.push(' ');
+                } else if n > 0 && (fields.keep || !fields.current.is_empty()) {
+                    fields.split();
+                }
+                fields.push_split(item);
+            }
+        },
+        Lookup::Scalar(value) => {
+            let value = value.unwrap_or_default();
+            if quoted {
+                fields.push_str_quoted(&value);
+            } else {
+                fields.push_split(&value);
+            }
+        },
+    }
+    (i, false)
+}
+
+/// Looks up parameter `name` written without braces
+fn simple(shell: &Shell, name: &str) -> Lookup {
+    match name {
+        "@" | "*" => Lookup::List(shell.positional().to_vec(), name == "*"),
+        name => Lookup::Scalar(shell.param(name)),
+    }
+}
+
+/// Evaluates the `expression` between `${` and `}`
+///
+/// Besides a parameter name, this may be an array element `a[i]`, all
+/// elements `a[@]` or `a[*]`, their keys `!a[@]`, an indirect reference
+/// `!name`, a length `#a` and a slice `a[@]:offset:length`.
+fn braced(shell: &Shell, expression: &str) -> Lookup {
+    let (length, rest) = match expression.strip_prefix('#') {
+        Some(rest) if !rest.is_empty() => (true, rest),
+        _ => (false, expression),
+    };
+    let (keys, rest) = match rest.strip_prefix('!') {
+        Some(rest) if !rest.is_empty() => (true, rest),
+        _ => (false, rest),
+    };
+
+    let name_length = match rest.chars().next() {
+        Some(c) if c.is_ascii_digit() => rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len()),
+        Some(c) if c.is_alphabetic() || c == '_' => {
+            rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len())
+        },
+        Some(c) => c.len_utf8(),
+        None => 0,
+    };
+    let (name, rest) = rest.split_at(name_length);
+
+    let (subscript, rest) = match rest.strip_prefix('[') {
+        Some(rest) => match closing_bracket(rest) {
+            Some(end) => (Some(&rest[..end]), &rest[end + 1..]),
+            None => (None, ""),
+        },
+        None => (None, rest),
+    };
+
+    let lookup = match (subscript, shell.value(name)) {
+        (Some("@" | "*"), Some(value)) if keys => Lookup::List(value.keys(), subscript == Some("*")),
+        (Some("@" | "*"), Some(value)) => Lookup::List(value.values(), subscript == Some("*")),
+        (Some("@" | "*"), None) => Lookup::List(Vec::new(), subscript == Some("*")),
+        (Some(subscript), Some(value)) => {
+            Lookup::Scalar(value.get(&shell.subscript(name, subscript)).cloned())
+        },
+        (Some(_), None) => Lookup::Scalar(None),
+        (None, _) if keys => Lookup::Scalar(shell.param(name).and_then(|name| shell.param(&name))),
+        (None, _) => simple(shell, name),
+    };
+
+    let lookup = match operate(shell, lookup, name, subscript, rest) {
+        Some(lookup) => lookup,
+        None => {
+            error!("rush: ${{{}}}: Bad substitution.", expression);
+            shell.fail_expansion();
+            return Lookup::Scalar(None);
+        },
+    };
+
+    match lookup {
+        Lookup::List(items, _) if length => Lookup::Scalar(Some(items.len().to_string())),
+        Lookup::Scalar(value) if length => {
+            Lookup::Scalar(Some(value.unwrap_or_default().chars().count().to_string()))
+        },
+        lookup => lookup,
+    }
+}
+
+/// Applies the operator in `rest`, which follows the parameter `name`, to
+/// the `lookup` of its value
+///
+/// Returns `None` if `rest` is not a valid operator.
+fn operate(shell: &Shell, mut lookup: Lookup, name: &str, subscript: Option<&str>, rest: &str) -> Option<Lookup> {
+    // With a colon, the default operators treat null values as unset.
+    let (null, operator) = match rest.strip_prefix(':') {
+        Some(operator) if operator.starts_with(['-', '=', '+', '?']) => (true, operator),
+        _ => (false, rest),
+    };
+    let set = match &lookup {
+        Lookup::Scalar(value) => value.as_ref().is_some_and(|value| !null || !value.is_empty()),
+        Lookup::List(items, _) => items.iter().any(|item| !null || !item.is_empty()),
+    };
+
+    let mut chars = operator.chars();
+    let lookup = match (chars.next(), chars.next()) {
+        (None, _) => lookup,
+        (Some('-'), _) if !set => Lookup::Scalar(Some(string(shell, &operator[1..]))),
+        (Some('='), _) if !set => {
+            let value = string(shell, &operator[1..]);
+            if subscript.is_some() || !name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+                return None;
+            }
+            shell.defer_assign(name, value.clone());
+            Lookup::Scalar(Some(value))
+        },
+        (Some('+'), _) if set => Lookup::Scalar(Some(string(shell, &operator[1..]))),
+        (Some('+'), _) => Lookup::Scalar(None),
+        (Some('?'), _) if !set => {
+            let message = match string(shell, &operator[1..]) {
+                message if message.is_empty() => String::from("Parameter null or not set."),
+                message => message,
+            };
+            error!("rush: {}: {}", name, message);
+            shell.fail_expansion();
+            Lookup::Scalar(None)
+        },
+        (Some('-' | '=' | '?'), _) => lookup,
+        (Some(':'), _) => {
+            if let (Lookup::List(items, _), "@" | "*", None) = (&mut lookup, name, subscript) {
+                items.insert(0, String::from(shell.arg0()));
+            }
+            slice(shell, lookup, &operator[1..])
+        },
+        (Some('#'), Some('#')) => map(lookup, |text| remove_prefix(text, &pattern(shell, &operator[2..]), true)),
+        (Some('#'), _) => map(lookup, |text| remove_prefix(text, &pattern(shell, &operator[1..]), false)),
+        (Some('%'), Some('%')) => map(lookup, |text| remove_suffix(text, &pattern(shell, &operator[2..]), true)),
+        (Some('%'), _) => map(lookup, |text| remove_suffix(text, &pattern(shell, &operator[1..]), false)),
+        (Some('/'), next) => {
+            let (anchor, spec) = match next {
+                Some(anchor @ ('/' | '#' | '%')) => (Some(anchor), &operator[2..]),
+                _ => (None, &operator[1..]),
+            };
+            let (find, replacement) = split_unquoted(spec, '/');
+            let find = pattern(shell, find);
+            let replacement = string(shell, replacement.unwrap_or(""));
+            map(lookup, |text| substitute(text, &find, &replacement, anchor))
+        },
+        (Some(case @ ('^' | ',')), next) => {
+            let all = next == Some(case);
+            let spec = if all { &operator[2..] } else { &operator[1..] };
+            let spec = if spec.is_empty() { String::from("?") } else { pattern(shell, spec) };
+            map(lookup, |text| change_case(text, &spec, case == '^', all))
+        },
+        (Some('@'), Some('Q')) if operator.len() == 2 => map(lookup, value::single_quote),
+        _ => return None,
+    };
+    Some(lookup)
+}
+
+/// Applies `f` to the value of `lookup` or each of its values
+fn map(lookup: Lookup, f: impl Fn(&str) -> String) -> Lookup {
+    match lookup {
+        Lookup::Scalar(value) => Lookup::Scalar(value.map(|value| f(&value))),
+        Lookup::List(items, joined) => Lookup::List(items.iter().map(|item| f(item)).collect(), joined),
+    }
+}
+
+/// Splits `text` at the first `separator` outside quotes and expansions
+fn split_unquoted(text: &str, separator: char) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut depth = 0;
+    let mut chars = text.char_indices();
+    while let Some((at, c)) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => (),
+            (_, '\\') => {
+                chars.next();
+            },
+            (Some(_), _) => (),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '{') => depth += 1,
+            (None, '}') => depth -= 1,
+            (None, c) if c == separator && depth == 0 => {
+                return (&text[..at], Some(&text[at + c.len_utf8()..]));
+            },
+            _ => (),
+        }
+    }
+    (text, None)
+}
+
+/// Returns the offsets of all character boundaries in `text`, both ends
+/// included
+fn boundaries(text: &str) -> Vec<usize> {
+    text.char_indices().map(|(at, _)| at).chain(std::iter::once(text.len())).collect()
+}
+
+/// Removes the shortest or `longest` prefix of `text` matching `pattern`
+fn remove_prefix(text: &str, pattern: &str, longest: bool) -> String {
+    let mut ends = boundaries(text);
+    if longest {
+        ends.reverse();
+    }
+    match ends.into_iter().find(|end| glob::matches(pattern, &text[..*end])) {
+        Some(end) => String::from(&text[end..]),
+        None => String::from(text),
+    }
+}
+
+/// Removes the shortest or `longest` suffix of `text` matching `pattern`
+fn remove_suffix(text: &str, pattern: &str, longest: bool) -> String {
+    let mut starts = boundaries(text);
+    if !longest {
+        starts.reverse();
+    }
+    match starts.into_iter().find(|start| glob::matches(pattern, &text[*start..])) {
+        Some(start) => String::from(&text[..start]),
+        None => String::from(text),
+    }
+}
+
+/// Replaces matches of `pattern` in `text` with `replacement`
+///
+/// Without an `anchor`, the longest match starting leftmost is replaced.
+/// With `/` all matches are, with `#` and `%` only one at the start or end.
+fn substitute(text: &str, pattern: &str, replacement: &str, anchor: Option<char>) -> String {
+    let offsets = boundaries(text);
+    match anchor {
+        Some('#') => {
+            let end = offsets.iter().rev().find(|end| glob::matches(pattern, &text[..**end]));
+            match end {
+                Some(end) => format!("{}{}", replacement, &text[*end..]),
+                None => String::from(text),
+            }
+        },
+        Some('%') => {
+            let start = offsets.iter().find(|start| glob::matches(pattern, &text[**start..]));
+            match start {
+                Some(start) => format!("{}{}", &text[..*start], replacement),
+                None => String::from(text),
+            }
+        },
+        _ if pattern.is_empty() => String::from(text),
+        _ => {
+            let all = anchor == Some('/');
+            let mut result = String::new();
+            let mut done = 0;
+            let mut i = 0;
+            while i + 1 < offsets.len() {
+                let start = offsets[i];
+                let end = offsets[i + 1..].iter().rev()
+                    .find(|end| glob::matches(pattern, &text[start..**end]));
+                match end {
+                    Some(end) if start >= done => {
+                        result.push_str(&text[done..start]);
+                        result.push_str(replacement);
+                        done = *end;
+                        if !all {
+                            break;
+                        }
+                        i = offsets.iter().position(|offset| offset == end).unwrap();
+                    },
+                    _ => i += 1,
+                }
+            }
+            result.push_str(&text[done..]);
+            result
+        },
+    }
+}
+
+/// Changes the case of the first or `all` characters of `text` matching
+/// `pattern`, to upper case if `upper`
+fn change_case(text: &str, pattern: &str, upper: bool, all: bool) -> String {
+    let mut result = String::with_capacity(text.len());
+    for (n, c) in text.chars().enumerate() {
+        if (all || n == 0) && glob::matches(pattern, c.encode_utf8(&mut [0; 4])) {
+            if upper {
+                result.extend(c.to_uppercase());
+            } else {
+                result.extend(c.to_lowercase());
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Finds the `]` closing a subscript in `text`, skipping nested brackets
+fn closing_bracket(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (at, c) in text.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth == 0 => return Some(at),
+            ']' => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Takes the part of `lookup` given by `spec`, which is `offset[:length]`
+///
+/// Lists are sliced by elements and scalars by characters. A negative
+/// offset counts from the end and a negative length leaves out that many
+/// from the end.
+fn slice(shell: &Shell, lookup: Lookup, spec: &str) -> Lookup {
+    let (offset, length) = match spec.split_once(':') {
+        Some((offset, length)) => (offset, Some(length)),
+        None => (spec, None),
+    };
+    let number = |text: &str| string(shell, text).trim().parse::<i64>().unwrap_or(0);
+    let range = |count: usize| {
+        let count = count as i64;
+        let offset = number(offset);
+        let start = if offset < 0 { count + offset } else { offset };
+        if start < 0 || start > count {
+            return (0, 0);
+        }
+        let end = match length.map(number) {
+            Some(length) if length < 0 && count + length < start => {
+                error!("rush: {}: Substring expression < 0.", length);
+                shell.fail_expansion();
+                return (0, 0);
+            },
+            Some(length) if length < 0 => count + length,
+            Some(length) => start + length,
+            None => count,
+        };
+        (start as usize, end.min(count) as usize)
+    };
+    match lookup {
+        Lookup::List(items, joined) => {
+            let (start, end) = range(items.len());
+            Lookup::List(items[start..end].to_vec(), joined)
+        },
+        Lookup::Scalar(value) => {
+            let value = value.unwrap_or_default();
+            let (start, end) = range(value.chars().count());
+            Lookup::Scalar(Some(value.chars().skip(start).take(end - start).collect()))
+        },
+    }
+}
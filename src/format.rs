@@ -0,0 +1,443 @@
+use super::value;
+
+/// Flavour of backslash escapes
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Escapes {
+    /// Escapes of `echo -e` and `printf %b`, with octal values as `\0nnn`
+    Echo,
+    /// Escapes of a `printf` format, with octal values as `\nnn`
+    Format,
+}
+
+/// Replaces backslash escapes in `text`
+///
+/// Returns the result and whether a `\c` cut off the rest of the output.
+/// Unknown escapes are kept as they are.
+pub fn unescape(text: &str, escapes: Escapes) -> (String, bool) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut result = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '\\' || i + 1 == chars.len() {
+            result.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        i += 2;
+        let c = match chars[i - 1] {
+            'a' => '\x07',
+            'b' => '\x08',
+            'e' | 'E' => '\x1b',
+            'f' => '\x0c',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'v' => '\x0b',
+            '\\' => '\\',
+            'c' => return (result, true),
+            '"' | '\'' if escapes == Escapes::Format => chars[i - 1],
+            '0' if escapes == Escapes::Echo => number(&chars, &mut i, 8, 3).unwrap_or('\0'),
+            '0'..='7' if escapes == Escapes::Format => {
+                i -= 1;
+                number(&chars, &mut i, 8, 3).unwrap_or('\0')
+            },
+            'x' => match number(&chars, &mut i, 16, 2) {
+                Some(c) => c,
+                None => {
+                    result.push_str("\\x");
+                    continue;
+                },
+            },
+            // Digits not making a character are left as written.
+            'u' | 'U' => {
+                let letter = chars[i - 1];
+                match number(&chars, &mut i, 16, if letter == 'u' { 4 } else { 8 }) {
+                    Some(c) => c,
+                    None => {
+                        result.push('\\');
+                        result.push(letter);
+                        continue;
+                    },
+                }
+            },
+            c => {
+                result.push('\\');
+                c
+            },
+        };
+        result.push(c);
+    }
+    (result, false)
+}
+
+/// Reads up to `max` digits in `radix` from `chars[*i]` on as a character
+///
+/// Returns `None`, reading nothing, if there are no digits or they do not
+/// make a character.
+fn number(chars: &[char], i: &mut usize, radix: u32, max: usize) -> Option<char> {
+    let digits: String = chars[*i..].iter()
+        .take(max)
+        .take_while(|c| c.is_digit(radix))
+        .collect();
+    let code = u32::from_str_radix(&digits, radix).ok()?;
+    // Octal escapes stand for bytes, which only go up to \377.
+    let c = char::from_u32(if radix == 8 { code & 0xff } else { code })?;
+    *i += digits.len();
+    Some(c)
+}
+
+/// Conversion specification such as `%-10s` or `%08.3f`
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    conversion: char,
+}
+
+impl Spec {
+    /// Pads `text` to the field width, putting zeros after `prefix` if
+    /// zero padding applies
+    fn pad(&self, prefix: &str, text: &str, zero: bool) -> String {
+        let len = prefix.chars().count() + text.chars().count();
+        let fill = self.width.saturating_sub(len);
+        if self.left {
+            format!("{}{}{}", prefix, text, " ".repeat(fill))
+        } else if zero && self.zero {
+            format!("{}{}{}", prefix, "0".repeat(fill), text)
+        } else {
+            format!("{}{}{}", " ".repeat(fill), prefix, text)
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        match (negative, self.plus, self.space) {
+            (true, _, _) => "-",
+            (false, true, _) => "+",
+            (false, false, true) => " ",
+            _ => "",
+        }
+    }
+
+    fn string(&self, text: &str) -> String {
+        let text: String = match self.precision {
+            Some(precision) => text.chars().take(precision).collect(),
+            None => String::from(text),
+        };
+        self.pad("", &text, false)
+    }
+
+    fn integer(&self, value: i64) -> String {
+        let magnitude = value.unsigned_abs();
+        let (prefix, digits) = match self.conversion {
+            'd' | 'i' => (self.sign(value < 0), magnitude.to_string()),
+            'u' => ("", (value as u64).to_string()),
+            'o' => ("", format!("{:o}", value as u64)),
+            'x' => (if self.alternate && value != 0 { "0x" } else { "" }, format!("{:x}", value as u64)),
+            'X' => (if self.alternate && value != 0 { "0X" } else { "" }, format!("{:X}", value as u64)),
+            _ => unreachable!(),
+        };
+        let mut digits = match self.precision {
+            Some(0) if value == 0 => String::new(),
+            Some(precision) if precision > digits.len() => "0".repeat(precision - digits.len()) + &digits,
+            _ => digits,
+        };
+        if self.conversion == 'o' && self.alternate && !digits.starts_with('0') {
+            digits.insert(0, '0');
+        }
+        self.pad(prefix, &digits, self.precision.is_none())
+    }
+
+    fn float(&self, value: f64) -> String {
+        let upper = self.conversion.is_ascii_uppercase();
+        let digits = if !value.is_finite() {
+            let text = if value.is_nan() { "nan" } else { "inf" };
+            let text = if upper { text.to_uppercase() } else { String::from(text) };
+            return self.pad(self.sign(value.is_sign_negative() && !value.is_nan()), &text, false);
+        } else {
+            let magnitude = value.abs();
+            let precision = self.precision.unwrap_or(6);
+            match self.conversion.to_ascii_lowercase() {
+                'f' => format!("{:.*}", precision, magnitude),
+                'e' => exponential(magnitude, precision),
+                'g' => general(magnitude, precision, self.alternate),
+                'a' => hexadecimal(magnitude, self.precision),
+                _ => unreachable!(),
+            }
+        };
+        let mut digits = if upper { digits.to_uppercase() } else { digits };
+        if self.alternate && !digits.contains('.') && !self.conversion.eq_ignore_ascii_case(&'g') {
+            let end = digits.find(|c: char| "eEpP".contains(c)).unwrap_or(digits.len());
+            digits.insert(end, '.');
+        }
+        self.pad(self.sign(value.is_sign_negative()), &digits, true)
+    }
+}
+
+/// Formats `value` as `d.ddde+xx` with `precision` digits after the point
+fn exponential(value: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = text.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+/// Formats `value` in the fixed or exponential notation, whichever suits
+/// its magnitude, with `precision` significant digits
+fn general(value: f64, precision: usize, alternate: bool) -> String {
+    let precision = precision.max(1);
+    let exponent = if value == 0.0 {
+        0
+    } else {
+        let text = format!("{:.*e}", precision - 1, value);
+        text[text.find('e').unwrap() + 1..].parse().unwrap()
+    };
+    let text = if exponent < -4 || exponent >= precision as i32 {
+        exponential(value, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+    };
+    if alternate {
+        return text;
+    }
+    // Trailing zeros of the fraction go, along with a bare point.
+    let (number, exponent) = match text.find('e') {
+        Some(e) => text.split_at(e),
+        None => (text.as_str(), ""),
+    };
+    let number = if number.contains('.') { number.trim_end_matches('0').trim_end_matches('.') } else { number };
+    format!("{}{}", number, exponent)
+}
+
+/// Formats `value` as a hexadecimal fraction with a binary exponent,
+/// such as `0x1.8p+1`
+fn hexadecimal(value: f64, precision: Option<usize>) -> String {
+    if value == 0.0 {
+        let fraction = precision.filter(|precision| *precision > 0).map_or(String::new(), |precision| format!(".{}", "0".repeat(precision)));
+        return format!("0x0{}p+0", fraction);
+    }
+    let bits = value.to_bits();
+    let mut exponent = ((bits >> 52) & 0x7ff) as i64;
+    let mut mantissa = bits & ((1 << 52) - 1);
+    let mut lead = 1;
+    if exponent == 0 {
+        // Subnormal numbers have no implicit leading one.
+        lead = 0;
+        exponent = 1;
+    }
+    exponent -= 1023;
+    let mut fraction = format!("{:013x}", mantissa);
+    match precision {
+        Some(precision) if precision < 13 => {
+            let shift = 4 * (13 - precision);
+            let rounded = (mantissa + (1 << (shift - 1))) >> shift;
+            if rounded >> (4 * precision) != 0 {
+                lead += 1;
+            }
+            mantissa = rounded & ((1 << (4 * precision)) - 1);
+            fraction = if precision == 0 { String::new() } else { format!("{:0width$x}", mantissa, width = precision) };
+        },
+        Some(precision) => fraction.push_str(&"0".repeat(precision - 13)),
+        None => fraction = String::from(fraction.trim_end_matches('0')),
+    }
+    let point = if fraction.is_empty() { "" } else { "." };
+    format!("0x{}{}{}p{}{}", lead, point, fraction, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+/// Arguments consumed by conversions of a format
+struct Args<'a> {
+    args: &'a [String],
+    next: usize,
+    /// Whether an argument failed to convert
+    failed: bool,
+}
+
+impl<'a> Args<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let arg = self.args.get(self.next)?;
+        self.next += 1;
+        Some(arg)
+    }
+
+    fn string(&mut self) -> &'a str {
+        self.next().unwrap_or("")
+    }
+
+    /// Converts the next argument to an integer the way C does, taking a
+    /// leading quote to mean the code of the character after it
+    fn integer(&mut self) -> i64 {
+        let arg = match self.next() {
+            Some(arg) => arg,
+            None => return 0,
+        };
+        let text = arg.trim_start();
+        if let Some(quoted) = text.strip_prefix(|c| c == '\'' || c == '"') {
+            return quoted.chars().next().map_or(0, |c| c as i64);
+        }
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (radix, digits) = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            (16, hex)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            (8, &digits[1..])
+        } else {
+            (10, digits)
+        };
+        match i64::from_str_radix(digits, radix) {
+            Ok(value) if negative => -value,
+            Ok(value) => value,
+            Err(_) => {
+                error!("printf: {}: Invalid number.", arg);
+                self.failed = true;
+                let valid: String = digits.chars().take_while(|c| c.is_digit(radix)).collect();
+                let value = i64::from_str_radix(&valid, radix).unwrap_or(0);
+                if negative { -value } else { value }
+            },
+        }
+    }
+
+    fn float(&mut self) -> f64 {
+        let arg = match self.args.get(self.next) {
+            Some(arg) => arg,
+            None => return 0.0,
+        };
+        match arg.trim().parse() {
+            Ok(value) => {
+                self.next += 1;
+                value
+            },
+            Err(_) => self.integer() as f64,
+        }
+    }
+}
+
+/// Formats `args` according to `format`, the way `printf` does
+///
+/// The format is applied again as long as there are arguments left, and
+/// missing arguments count as empty or zero. Returns the output and
+/// whether all arguments converted. A malformed conversion fails.
+pub fn printf(format: &str, args: &[String]) -> Result<(String, bool), String> {
+    let mut args = Args { args, next: 0, failed: false };
+    let mut output = String::new();
+    loop {
+        let start = args.next;
+        if apply(format, &mut args, &mut output)? {
+            break;
+        }
+        if args.next == start || args.next >= args.args.len() {
+            break;
+        }
+    }
+    Ok((output, !args.failed))
+}
+
+/// Applies `format` once, appending to `output`
+///
+/// Returns whether a `\c` ended the output.
+fn apply(format: &str, args: &mut Args, output: &mut String) -> Result<bool, String> {
+    let chars: Vec<char> = format.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '%' if chars.get(i + 1) == Some(&'%') => {
+                output.push('%');
+                i += 2;
+            },
+            '%' => {
+                let start = i;
+                i += 1;
+                let mut spec = Spec {
+                    left: false,
+                    plus: false,
+                    space: false,
+                    alternate: false,
+                    zero: false,
+                    width: 0,
+                    precision: None,
+                    conversion: ' ',
+                };
+                while let Some(flag) = chars.get(i) {
+                    match flag {
+                        '-' => spec.left = true,
+                        '+' => spec.plus = true,
+                        ' ' => spec.space = true,
+                        '#' => spec.alternate = true,
+                        '0' => spec.zero = true,
+                        _ => break,
+                    }
+                    i += 1;
+                }
+                let count = |i: &mut usize, args: &mut Args| -> Option<i64> {
+                    if chars.get(*i) == Some(&'*') {
+                        *i += 1;
+                        return Some(args.integer());
+                    }
+                    let digits: String = chars[*i..].iter().take_while(|c| c.is_ascii_digit()).collect();
+                    *i += digits.len();
+                    digits.parse().ok()
+                };
+                if let Some(width) = count(&mut i, args) {
+                    spec.left |= width < 0;
+                    spec.width = width.unsigned_abs() as usize;
+                }
+                if chars.get(i) == Some(&'.') {
+                    i += 1;
+                    spec.precision = Some(count(&mut i, args).unwrap_or(0).max(0) as usize);
+                }
+                spec.conversion = match chars.get(i) {
+                    Some(c) => *c,
+                    None => return Err(format!("{}: Missing conversion.", chars[start..].iter().collect::<String>())),
+                };
+                i += 1;
+                match spec.conversion {
+                    's' => output.push_str(&spec.string(args.string())),
+                    'q' => output.push_str(&spec.string(&value::quote(args.string()))),
+                    'c' => output.push_str(&spec.string(&args.string().chars().take(1).collect::<String>())),
+                    'b' => {
+                        let (text, stop) = unescape(args.string(), Escapes::Echo);
+                        output.push_str(&spec.string(&text));
+                        if stop {
+                            return Ok(true);
+                        }
+                    },
+                    'd' | 'i' | 'o' | 'u' | 'x' | 'X' => output.push_str(&spec.integer(args.integer())),
+                    'f' | 'F' | 'e' | 'E' | 'g' | 'G' | 'a' | 'A' => output.push_str(&spec.float(args.float())),
+                    _ => return Err(format!("{}: Invalid conversion.", chars[start..i].iter().collect::<String>())),
+                }
+            },
+            '\\' => {
+                let end = escape_end(&chars, i);
+                let (text, stop) = unescape(&chars[i..end].iter().collect::<String>(), Escapes::Format);
+                output.push_str(&text);
+                if stop {
+                    return Ok(true);
+                }
+                i = end;
+            },
+            c => {
+                output.push(c);
+                i += 1;
+            },
+        }
+    }
+    Ok(false)
+}
+
+/// Returns the index following the escape sequence at `chars[start]`
+fn escape_end(chars: &[char], start: usize) -> usize {
+    let (radix, max) = match chars.get(start + 1) {
+        Some('0'..='7') => (8, 3),
+        Some('x') => (16, 2),
+        Some('u') => (16, 4),
+        Some('U') => (16, 8),
+        Some(_) => return start + 2,
+        None => return start + 1,
+    };
+    let first = if radix == 8 { start + 1 } else { start + 2 };
+    first + chars[first..].iter().take(max).take_while(|c| c.is_digit(radix)).count()
+}
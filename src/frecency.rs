@@ -0,0 +1,157 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Total rank above which all entries are aged
+const MAX_RANK: f64 = 9000.0;
+
+/// Factor applied to every rank when the database is aged
+const AGING: f64 = 0.99;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+pub struct Entry {
+    pub path: String,
+    pub rank: f64,
+    pub time: u64,
+}
+
+impl Entry {
+    /// Scores the entry by its rank weighted by how recently it was visited
+    pub fn score(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.time);
+        let weight = if age < HOUR {
+            4.0
+        } else if age < DAY {
+            2.0
+        } else if age < WEEK {
+            0.5
+        } else {
+            0.25
+        };
+        self.rank * weight
+    }
+
+    /// Checks whether all `fragments` occur in the path in the given order,
+    /// the last of them in the final component
+    fn matches(&self, fragments: &[String]) -> bool {
+        let fold = !fragments.iter().any(|fragment| fragment.chars().any(char::is_uppercase));
+        let path = if fold { self.path.to_lowercase() } else { self.path.clone() };
+
+        let mut rest = path.as_str();
+        for (i, fragment) in fragments.iter().enumerate() {
+            let fragment = if fold { fragment.to_lowercase() } else { fragment.clone() };
+            let found = if i + 1 == fragments.len() {
+                rest.rfind(&fragment)
+                    .filter(|at| !rest[at + fragment.len()..].contains('/'))
+            } else {
+                rest.find(&fragment)
+            };
+            match found {
+                Some(at) => rest = &rest[at + fragment.len()..],
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+/// Database of visited directories scored by frequency and recency
+///
+/// The database lives in a file shared by all running shells, so every
+/// operation reads it afresh and writes changes back right away.
+pub struct Frecency {
+    path: PathBuf,
+}
+
+impl Frecency {
+    pub fn new(path: &Path) -> Frecency {
+        Frecency { path: path.to_path_buf() }
+    }
+
+    /// Records a visit of directory `dir`
+    pub fn add(&self, dir: &str) {
+        let mut entries = self.load();
+        let now = now();
+        if let Some(entry) = entries.iter_mut().find(|entry| entry.path == dir) {
+            entry.rank += 1.0;
+            entry.time = now;
+        } else {
+            entries.push(Entry { path: String::from(dir), rank: 1.0, time: now });
+        }
+
+        if entries.iter().map(|entry| entry.rank).sum::<f64>() > MAX_RANK {
+            for entry in &mut entries {
+                entry.rank *= AGING;
+            }
+            entries.retain(|entry| entry.rank >= 1.0);
+        }
+        self.save(&entries);
+    }
+
+    /// Returns entries matching `fragments` with their scores, best first
+    ///
+    /// Entries of directories that no longer exist are dropped from the
+    /// database on the way.
+    pub fn query(&self, fragments: &[String]) -> Vec<(f64, String)> {
+        let mut entries = self.load();
+        let count = entries.len();
+        entries.retain(|entry| Path::new(&entry.path).is_dir());
+        if entries.len() != count {
+            self.save(&entries);
+        }
+
+        let now = now();
+        let mut matches: Vec<_> = entries.into_iter()
+            .filter(|entry| entry.matches(fragments))
+            .map(|entry| (entry.score(now), entry.path))
+            .collect();
+        matches.sort_by(|a, b| b.0.total_cmp(&a.0));
+        matches
+    }
+
+    fn load(&self) -> Vec<Entry> {
+        let data = fs::read_to_string(&self.path).unwrap_or_default();
+        data.lines()
+            .filter_map(|line| {
+                let mut fields = line.rsplitn(3, '|');
+                let time = fields.next()?.parse().ok()?;
+                // A corrupt rank must not spoil the scores of all entries.
+                let rank = fields.next()?.parse().ok().filter(|rank: &f64| rank.is_finite())?;
+                let path = String::from(fields.next()?);
+                Some(Entry { path, rank, time })
+            })
+            .collect()
+    }
+
+    /// Writes `entries` to a temporary file and moves it over the database,
+    /// so concurrent readers never see a partial write
+    fn save(&self, entries: &[Entry]) {
+        if let Some(parent) = self.path.parent() {
+            if fs::create_dir_all(parent).is_err() {
+                return;
+            }
+        }
+
+        let tmp = self.path.with_extension(format!("{}", std::process::id()));
+        let written = fs::File::create(&tmp).and_then(|mut file| {
+            for entry in entries {
+                writeln!(file, "{}|{}|{}", entry.path, entry.rank, entry.time)?;
+            }
+            Ok(())
+        });
+        if written.is_err() || fs::rename(&tmp, &self.path).is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,156 @@
+/// Piece of a shell pattern
+enum Token {
+    Char(char),
+    /// `?`, any single character
+    Any,
+    /// `*`, any sequence of characters
+    Star,
+    /// Bracket expression such as `[a-z]` or `[!0-9]`
+    Class { negated: bool, items: Vec<ClassItem> },
+}
+
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    /// Named class such as `[:alpha:]`
+    Named(String),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            ClassItem::Char(item) => *item == c,
+            ClassItem::Range(from, to) => (*from..=*to).contains(&c),
+            ClassItem::Named(name) => match name.as_str() {
+                "alnum" => c.is_alphanumeric(),
+                "alpha" => c.is_alphabetic(),
+                "blank" => c == ' ' || c == '\t',
+                "cntrl" => c.is_control(),
+                "digit" => c.is_ascii_digit(),
+                "graph" => !c.is_whitespace() && !c.is_control(),
+                "lower" => c.is_lowercase(),
+                "print" => !c.is_control(),
+                "punct" => c.is_ascii_punctuation(),
+                "space" => c.is_whitespace(),
+                "upper" => c.is_uppercase(),
+                "xdigit" => c.is_ascii_hexdigit(),
+                _ => false,
+            },
+        }
+    }
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Char(token) => *token == c,
+            Token::Any => true,
+            Token::Star => unreachable!(),
+            Token::Class { negated, items } => items.iter().any(|item| item.matches(c)) != *negated,
+        }
+    }
+}
+
+/// Splits `pattern` into tokens
+///
+/// A backslash makes the next character literal. A `[` without a closing
+/// `]` stands for itself.
+fn tokenize(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                tokens.push(Token::Char(chars[i + 1]));
+                i += 1;
+            },
+            '?' => tokens.push(Token::Any),
+            '*' => {
+                if !matches!(tokens.last(), Some(Token::Star)) {
+                    tokens.push(Token::Star);
+                }
+            },
+            '[' => match class(&chars, i + 1) {
+                Some((token, next)) => {
+                    tokens.push(token);
+                    i = next - 1;
+                },
+                None => tokens.push(Token::Char('[')),
+            },
+            c => tokens.push(Token::Char(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// Parses the bracket expression starting after `[` at `chars[start]`
+///
+/// Returns the class and the index following its closing `]`.
+fn class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start;
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut items = Vec::new();
+    let first = i;
+    loop {
+        let c = *chars.get(i)?;
+        match c {
+            ']' if i > first => return Some((Token::Class { negated, items }, i + 1)),
+            '[' if chars.get(i + 1) == Some(&':') => {
+                let rest: String = chars[i + 2..].iter().collect();
+                let end = rest.find(":]")?;
+                items.push(ClassItem::Named(String::from(&rest[..end])));
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            },
+            '\\' => {
+                i += 1;
+                items.push(ClassItem::Char(*chars.get(i)?));
+            },
+            c if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|to| *to != ']') => {
+                items.push(ClassItem::Range(c, chars[i + 2]));
+                i += 2;
+            },
+            c => items.push(ClassItem::Char(c)),
+        }
+        i += 1;
+    }
+}
+
+/// Checks whether the whole of `text` matches shell pattern `pattern`
+///
+/// Patterns are made of `*`, `?`, bracket expressions and literal
+/// characters, which may be escaped by a backslash.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let tokens = tokenize(pattern);
+    let text: Vec<char> = text.chars().collect();
+
+    // On a mismatch, let the last star take one more character and retry.
+    let mut backtrack = None;
+    let (mut t, mut c) = (0, 0);
+    while c < text.len() {
+        match tokens.get(t) {
+            Some(Token::Star) => {
+                backtrack = Some((t, c));
+                t += 1;
+            },
+            Some(token) if token.matches(text[c]) => {
+                t += 1;
+                c += 1;
+            },
+            _ => match backtrack {
+                Some((star, from)) => {
+                    backtrack = Some((star, from + 1));
+                    t = star + 1;
+                    c = from + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+    tokens[t..].iter().all(|token| matches!(token, Token::Star))
+}
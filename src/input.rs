@@ -1,85 +1,133 @@
-use std::collections::VecDeque;
-use std::iter::{Iterator, FromIterator};
-
-use super::parser::{bash, Sequence};
+use super::parser::{bash, List, ParseError};
 
+/// Line editor buffer
+///
+/// Edits only touch the text around the cursor. The buffer is parsed on
+/// demand, which the shell does when the line is submitted.
 pub struct Input {
-    raw: (VecDeque<char>, VecDeque<char>),
-    parsed: Sequence,
+    buffer: String,
+    /// Byte offset of the cursor in `buffer`
+    cursor: usize,
 }
 
 impl Input {
     pub fn new() -> Input {
-        Input { 
-            raw: (VecDeque::new(), VecDeque::new()),
-            parsed: Sequence::new()
+        Input {
+            buffer: String::new(),
+            cursor: 0,
         }
     }
 
     pub fn clear(&mut self) {
-        self.raw.0.clear();
-        self.raw.1.clear();
-        self.parse();
+        self.buffer.clear();
+        self.cursor = 0;
     }
 
     pub fn push(&mut self, c: char) {
-        self.raw.0.push_back(c);
-        self.parse();
+        self.buffer.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Parses the buffer, borrowing words from it
+    pub fn get(&self, comments: bool) -> Result<List<'_>, ParseError> {
+        bash::parse(&self.buffer, comments)
     }
 
-    pub fn get(&self) -> Sequence {
-        self.parsed.clone()
+    /// Empties the buffer, returning its contents
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+        std::mem::take(&mut self.buffer)
     }
 
     pub fn is_empty(&self) -> bool {
-        self.raw.0.is_empty() && self.raw.1.is_empty()
+        self.buffer.is_empty()
     }
 
     pub fn pop_prev(&mut self) -> Option<char> {
-        let popped = self.raw.0.pop_back();
-        self.parse();
-        popped
+        let c = self.buffer[..self.cursor].chars().next_back()?;
+        self.cursor -= c.len_utf8();
+        self.buffer.remove(self.cursor);
+        Some(c)
     }
 
     pub fn pop_next(&mut self) -> Option<char> {
-        let popped = self.raw.1.pop_front();
-        self.parse();
-        popped
+        self.buffer[self.cursor..].chars().next()?;
+        Some(self.buffer.remove(self.cursor))
     }
 
-    pub fn raw(&self) -> String {
-        String::from_iter(self.raw.0.iter().chain(self.raw.1.iter()))
+    pub fn raw(&self) -> &str {
+        &self.buffer
     }
 
     pub fn set(&mut self, raw: &str) {
-        self.raw.0 = VecDeque::from_iter(raw.chars());
-        self.raw.1.clear();
-        self.parse();
+        self.buffer.clear();
+        self.buffer.push_str(raw);
+        self.cursor = self.buffer.len();
     }
 
     pub fn left(&mut self) -> bool {
-        if let Some(c) = self.raw.0.pop_back() {
-            self.raw.1.push_front(c);
-            true
-        } else {
-            false
+        match self.buffer[..self.cursor].chars().next_back() {
+            Some(c) => {
+                self.cursor -= c.len_utf8();
+                true
+            },
+            None => false,
         }
     }
 
     pub fn right(&mut self) -> bool {
-        if let Some(c) = self.raw.1.pop_front() {
-            self.raw.0.push_back(c);
-            true
-        } else {
-            false
+        match self.buffer[self.cursor..].chars().next() {
+            Some(c) => {
+                self.cursor += c.len_utf8();
+                true
+            },
+            None => false,
         }
     }
 
-    pub fn position(&self) -> usize {
-        self.raw.1.len()
+    /// Moves the cursor to the same column of the previous line
+    ///
+    /// Returns `false` if the cursor is on the first line.
+    pub fn up(&mut self) -> bool {
+        let before = &self.buffer[..self.cursor];
+        let newline = match before.rfind('\n') {
+            Some(newline) => newline,
+            None => return false,
+        };
+        let column = before[newline + 1..].chars().count();
+        let start = before[..newline].rfind('\n').map_or(0, |prev| prev + 1);
+        self.cursor = start + byte_offset(&self.buffer[start..newline], column);
+        true
+    }
+
+    /// Moves the cursor to the same column of the next line
+    ///
+    /// Returns `false` if the cursor is on the last line.
+    pub fn down(&mut self) -> bool {
+        let start = self.buffer[..self.cursor].rfind('\n').map_or(0, |newline| newline + 1);
+        let column = self.buffer[start..self.cursor].chars().count();
+        let next = match self.buffer[self.cursor..].find('\n') {
+            Some(newline) => self.cursor + newline + 1,
+            None => return false,
+        };
+        let end = self.buffer[next..].find('\n').map_or(self.buffer.len(), |newline| next + newline);
+        self.cursor = next + byte_offset(&self.buffer[next..end], column);
+        true
     }
 
-    fn parse(&mut self) {
-        self.parsed = bash::parse(self.raw())
+    /// Checks whether the input is an unfinished construct needing more lines
+    pub fn incomplete(&self, comments: bool) -> bool {
+        self.get(comments).err().is_some_and(|error| error.incomplete())
     }
+
+    /// Returns the number of characters after the cursor
+    pub fn position(&self) -> usize {
+        self.buffer[self.cursor..].chars().count()
+    }
+}
+
+/// Returns the byte offset of character `column` in `line`, or its length
+/// if the line is shorter
+fn byte_offset(line: &str, column: usize) -> usize {
+    line.char_indices().nth(column).map_or(line.len(), |(offset, _)| offset)
 }
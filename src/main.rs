@@ -1,64 +1,102 @@
+/// Prints an error message to standard error, as `eprintln!` does
+///
+/// While a file runs, the message is prefixed with its name and the line
+/// of the running command.
+macro_rules! error {
+    ($($arg:tt)*) => {
+        eprintln!("{}{}", $crate::shell().location(), format_args!($($arg)*))
+    };
+}
+
+mod condition;
 mod config;
 mod builtin;
 mod executor;
+mod expand;
+mod format;
+mod frecency;
+mod glob;
 mod parser;
 mod input;
 mod shell;
+mod value;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::io::Write;
 use std::process::exit;
 use std::sync::atomic::{self, AtomicU32};
 
+use nix::errno::Errno;
 use nix::sys::signal::{self, Signal, SigHandler};
+use nix::unistd;
 use directories::ProjectDirs;
 use clap::Parser;
 
+use parser::bash;
 use shell::{Action, Shell};
 use termion::input::TermRead;
 use termion::raw::IntoRawMode;
 
-static mut TERM_SIZE: AtomicU32 = AtomicU32::new(0);
+static TERM_SIZE: AtomicU32 = AtomicU32::new(0);
 static mut SHELL: Option<Shell> = None;
 
-extern fn handle_sigwinch(_: nix::libc::c_int) {
-    let new_size = termion::terminal_size().unwrap();
-    let new_size = ((new_size.0 as u32) << 16) | (new_size.1 as u32);
-    unsafe { TERM_SIZE.store(new_size, atomic::Ordering::Relaxed); }
+/// Returns the global shell
+///
+/// Builtins running inside the shell process reach its state through here.
+#[allow(static_mut_refs)]
+pub fn shell() -> &'static mut Shell {
+    unsafe { SHELL.as_mut().unwrap() }
 }
 
-/// Prints terminal prompt
-/// 
-/// Nothing more.
-fn print_prompt(prompt: &str) {
-    print!("\r{}", prompt);
+extern "C" fn handle_sigwinch(_: nix::libc::c_int) {
+    // Without a terminal there is no size, so the last one known stays
+    if let Ok(new_size) = termion::terminal_size() {
+        let new_size = ((new_size.0 as u32) << 16) | (new_size.1 as u32);
+        TERM_SIZE.store(new_size, atomic::Ordering::Relaxed);
+    }
 }
 
-/// Prints current terminal input
+/// Prints terminal prompt and current input
 /// 
-/// Moves cursor back by `prev_position`, prints `line`, positions cursor back
-/// by `position` characters.
-fn print_line(line: &str, position: usize, prev_position: usize) -> usize {
-    for _ in 0..prev_position {
-        print!("\x08");
+/// Moves cursor up by `prev_row` rows to the start of the input, prints
+/// `prompt` and `line`, prefixing every continuation line with
+/// `continuation`, and positions cursor back by `position` characters.
+/// Returns the row of the input the cursor ends up on.
+fn print_input(prompt: &str, continuation: &str, line: &str, position: usize, prev_row: usize) -> usize {
+    if prev_row > 0 {
+        print!("{}", termion::cursor::Up(prev_row as u16));
     }
+    print!("\r{}{}", termion::clear::AfterCursor, prompt);
+
+    let lines: Vec<_> = line.split('\n').collect();
+    print!("{}", lines.join(&format!("\r\n{}", continuation)));
 
-    print!("{}{} \x08", termion::clear::AfterCursor, line);
+    let before: String = line.chars().take(line.chars().count() - position).collect();
+    let row = before.matches('\n').count();
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count()
+        + if row == 0 { prompt.chars().count() } else { continuation.chars().count() };
 
-    for _ in 0..position {
-        print!("\x08");
+    if lines.len() - 1 > row {
+        print!("{}", termion::cursor::Up((lines.len() - 1 - row) as u16));
+    }
+    print!("\r");
+    if column > 0 {
+        print!("{}", termion::cursor::Right(column as u16));
     }
 
-    line.len() - position
+    row
 }
 
-
 fn interactive() {
     unsafe { signal::signal(Signal::SIGINT, SigHandler::SigIgn) }.unwrap();
     unsafe { signal::signal(Signal::SIGWINCH, SigHandler::Handler(handle_sigwinch)) }.unwrap();
     handle_sigwinch(0);
 
-    let shell = unsafe { SHELL.as_mut().unwrap() };
+    if !unistd::isatty(0).unwrap_or(false) {
+        prompt_lines();
+    }
+
+    let shell = shell();
 
     'command: loop {
         let stdin = std::io::stdin();
@@ -67,8 +105,7 @@ fn interactive() {
             exit(1);
         });
 
-        print_prompt(&shell.prompt());
-        let mut prev_position = print_line(&shell.line(), shell.position(), 0);
+        let mut row = print_input(&shell.prompt(), &shell.continuation_prompt(), shell.line(), shell.position(), 0);
         stdout.flush().unwrap();
 
         'event: for event in stdin.events() {
@@ -88,11 +125,11 @@ fn interactive() {
                 }
             }
 
-            prev_position = print_line(&shell.line(), shell.position(), prev_position);
+            row = print_input(&shell.prompt(), &shell.continuation_prompt(), shell.line(), shell.position(), row);
             stdout.flush().unwrap();
         }
 
-        print_line(&shell.line(), 0, prev_position);
+        print_input(&shell.prompt(), &shell.continuation_prompt(), shell.line(), 0, row);
         print!("\r\n");
         stdout.flush().unwrap();
 
@@ -100,40 +137,230 @@ fn interactive() {
 
         shell.process();
     }
+
+    let status = shell.status() as u8;
+    shell.exit(status);
+}
+
+/// Runs commands interactively from standard input that is not a terminal
+///
+/// There are no lines to edit, so each is read as it is, after printing
+/// the prompt on standard error. Lines are collected until they form a
+/// complete construct, as `read_stdin` does.
+fn prompt_lines() -> ! {
+    let shell = shell();
+    let mut buffer = Vec::new();
+    loop {
+        let prompt = if buffer.is_empty() { shell.prompt() } else { shell.continuation_prompt() };
+        eprint!("{}", prompt);
+        let eof = !read_line(&mut buffer);
+
+        let source = String::from_utf8_lossy(&buffer).into_owned();
+        if !eof && bash::parse(&source, true).err().is_some_and(|error| error.incomplete()) {
+            continue;
+        }
+        if !source.trim().is_empty() {
+            shell.set_line(source.strip_suffix('\n').unwrap_or(&source));
+            shell.process();
+        }
+        buffer.clear();
+
+        if eof {
+            eprintln!();
+            let status = shell.status() as u8;
+            shell.exit(status);
+        }
+    }
 }
 
 fn execute(command: &str) {
-    let shell = unsafe { SHELL.as_mut().unwrap() };
+    let shell = shell();
     shell.set_line(command);
-    exit(shell.process() as i32);
+    let retcode = shell.process();
+    shell.exit(retcode as u8);
+}
+
+/// Appends a line read from standard input to `buffer`
+///
+/// Input is read byte by byte, so that commands reading standard input
+/// themselves get to see everything after the line. Returns `false` at the
+/// end of input, having appended whatever came before it.
+fn read_line(buffer: &mut Vec<u8>) -> bool {
+    let mut byte = [0u8];
+    loop {
+        match unistd::read(0, &mut byte) {
+            Ok(1) => {
+                buffer.push(byte[0]);
+                if byte[0] == b'\n' {
+                    return true;
+                }
+            },
+            Err(Errno::EINTR) => continue,
+            _ => return false,
+        }
+    }
+}
+
+/// Reads and runs commands from standard input until it is exhausted
+///
+/// Lines are collected until they form a complete construct.
+fn read_stdin() {
+    let shell = shell();
+    let mut retcode = 0;
+    let mut buffer = Vec::new();
+    let mut line = 0;
+    let mut first_line = 1;
+    loop {
+        let eof = !read_line(&mut buffer);
+        if !eof {
+            line += 1;
+        }
+
+        let source = String::from_utf8_lossy(&buffer).into_owned();
+        if !eof && bash::parse(&source, true).err().is_some_and(|error| error.incomplete()) {
+            continue;
+        }
+        if !source.trim().is_empty() {
+            retcode = shell.run_source(&source, "stdin", first_line);
+        }
+        buffer.clear();
+        first_line = line + 1;
+
+        if eof {
+            shell.exit(retcode as u8);
+        }
+    }
+}
+
+fn run_script(script: &str, args: &[String]) {
+    let shell = shell();
+    shell.set_arg0(script);
+    shell.set_positional(args.to_vec());
+    match shell.run_file(Path::new(script)) {
+        Some(retcode) => shell.exit(retcode as u8),
+        None => {
+            eprintln!("rush: Could not read script {}.", script);
+            exit(127);
+        },
+    }
+}
+
+/// Sources startup files
+///
+/// Login shells read `/etc/profile` and `~/.profile`, interactive shells
+/// `~/.rushrc` (or the `--rcfile`) and, in POSIX mode, the file named by
+/// `$ENV`. Missing files are skipped silently unless named explicitly.
+/// Errors in them are reported without ending the shell.
+fn startup(args: &Args, login: bool, interactive: bool) {
+    let shell = shell();
+    let home = shell.var("HOME").map(PathBuf::from);
+    shell.set_starting(true);
+
+    if login && !args.noprofile {
+        shell.run_file(Path::new("/etc/profile"));
+        if let Some(home) = &home {
+            shell.run_file(&home.join(".profile"));
+        }
+    }
+
+    if interactive && !args.norc {
+        if let Some(rcfile) = &args.rcfile {
+            if shell.run_file(Path::new(rcfile)).is_none() {
+                eprintln!("rush: Could not read rc file {}.", rcfile);
+            }
+        } else if let Some(home) = &home {
+            shell.run_file(&home.join(".rushrc"));
+        }
+
+        if args.posix {
+            if let Some(env) = shell.var("ENV").filter(|env| !env.is_empty()).cloned() {
+                shell.run_file(Path::new(&env));
+            }
+        }
+    }
+    shell.set_starting(false);
 }
 
 #[derive(Parser)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, trailing_var_arg = true)]
 struct Args {
     #[clap(short, long)]
     command: Option<String>,
 
     #[clap(long)]
     config: Option<String>,
+
+    /// Act as a login shell
+    #[clap(short, long)]
+    login: bool,
+
+    /// Do not read ~/.rushrc
+    #[clap(long)]
+    norc: bool,
+
+    /// Do not read /etc/profile and ~/.profile
+    #[clap(long)]
+    noprofile: bool,
+
+    /// Read FILE instead of ~/.rushrc
+    #[clap(long, value_name = "FILE")]
+    rcfile: Option<String>,
+
+    /// Follow POSIX more closely, reading $ENV on interactive startup
+    #[clap(long)]
+    posix: bool,
+
+    /// Script to run instead of reading commands interactively
+    script: Option<String>,
+
+    /// Arguments passed to the script
+    #[clap(allow_hyphen_values = true)]
+    args: Vec<String>,
+
+    /// Read commands from standard input, taking all operands as arguments
+    #[clap(short = 's')]
+    stdin: bool,
+
+    /// Run interactively even if standard input is not a terminal
+    #[clap(short = 'i')]
+    interactive: bool,
 }
 
 fn main() {
     let args = Args::parse();
 
-    let config = args.config.map(PathBuf::from).or({
-        if let Some(path) = ProjectDirs::from("", "", "rush") {
-            Some(path.config_dir().join("config.toml"))
-        } else {
-            None
-        }
+    let dirs = ProjectDirs::from("", "", "rush");
+    let config = args.config.as_ref().map(PathBuf::from).or_else(|| {
+        dirs.as_ref().map(|path| path.config_dir().join("config.toml"))
     });
+    let data = dirs.as_ref().map(|path| path.data_dir().to_path_buf());
 
-    unsafe { SHELL = Some(Shell::new(config.as_ref().map(PathBuf::as_path))) };
+    unsafe { SHELL = Some(Shell::new(config.as_deref(), data.as_deref())) };
 
-    if let Some(command) = args.command {
-        execute(&command);
-    } else {
-        interactive();
+    let login = args.login || std::env::args_os()
+        .next()
+        .is_some_and(|arg0| arg0.to_string_lossy().starts_with('-'));
+    let from_stdin = args.command.is_none() && (args.stdin || args.script.is_none());
+    let tty = unistd::isatty(0).unwrap_or(false);
+    let is_interactive = args.interactive || (from_stdin && tty);
+    let flags = [(is_interactive, 'i'), (args.command.is_some(), 'c'), (from_stdin, 's')];
+    shell().set_flags(&flags.iter().filter(|(set, _)| *set).map(|(_, flag)| *flag).collect::<String>());
+    startup(&args, login, is_interactive);
+
+    if let Some(command) = &args.command {
+        if let Some(arg0) = &args.script {
+            shell().set_arg0(arg0);
+        }
+        shell().set_positional(args.args.clone());
+        execute(command);
+    } else if from_stdin {
+        shell().set_positional(args.script.iter().chain(args.args.iter()).cloned().collect());
+        if is_interactive {
+            interactive();
+        } else {
+            read_stdin();
+        }
+    } else if let Some(script) = &args.script {
+        run_script(script, &args.args);
     }
 }
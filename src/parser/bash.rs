@@ -1,141 +1,439 @@
+use std::borrow::Cow;
+
 use pest::Parser;
+use pest::error::{Error, ErrorVariant, InputLocation, LineColLocation};
 use pest::iterators::Pair;
 use pest_derive::Parser;
 
-use super::{Sequence, SequenceKind, Command, CommandKind, Atom, AtomKind};
+use super::{
+    Assign, AssignValue, AndOr, AndOrKind, Command, CommandKind, CompoundCommand, Condition, List, ParseError, Pipeline,
+    Redirect, SimpleCommand, Span,
+};
 
 #[derive(Parser)]
 #[grammar = "parser/bash.pest"]
 struct BashParser;
 
-pub fn parse(input: String) -> Sequence {
-    let mut sequence = Sequence::new();
-    let parsed = BashParser::parse(Rule::line, &input);
-    if parsed.is_err() {
-        return sequence;
+/// Parses `input` into a list of commands
+///
+/// A `#` at the start of a word begins a comment running to the end of the
+/// line, unless `comments` is false, in which case it is an ordinary
+/// character. The returned list borrows words from `input`.
+pub fn parse(input: &str, comments: bool) -> Result<List<'_>, ParseError> {
+    let rule = if comments { Rule::program } else { Rule::program_literal };
+    let mut parsed = BashParser::parse(rule, input)
+        .map_err(|error| parse_error(input, comments, error))?;
+
+    match parsed.next() {
+        Some(pair) if pair.as_rule() == Rule::list => Ok(parse_list(pair)),
+        _ => Ok(List::new()),
     }
+}
 
-    let mut seq = SequenceKind::Seq;
+/// Finds the words of each simple command in `input`, in order
+///
+/// This is where aliases are expanded, the first word of a command in
+/// particular.
+pub fn command_words(input: &str, comments: bool) -> Result<Vec<Vec<Span>>, ParseError> {
+    let rule = if comments { Rule::program } else { Rule::program_literal };
+    let parsed = BashParser::parse(rule, input)
+        .map_err(|error| parse_error(input, comments, error))?;
 
-    for pair in parsed.unwrap() {
-        match pair.as_rule() {
-            Rule::command => sequence.add(seq, parse_command(pair)),
-            Rule::separator => {
-                seq = parse_separator(pair);
+    Ok(parsed.flatten()
+        .filter(|pair| pair.as_rule() == Rule::simple_command)
+        .map(|command| command.into_inner()
+            .filter(|pair| pair.as_rule() == Rule::word)
+            .map(|word| span(&word))
+            .collect())
+        .collect())
+}
+
+fn parse_error(input: &str, comments: bool, error: Error<Rule>) -> ParseError {
+    let (line, column) = match error.line_col {
+        LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => pos,
+    };
+    let mut expected: Vec<String> = match &error.variant {
+        ErrorVariant::ParsingError { positives, .. } => positives.iter()
+            .map(|rule| String::from(describe(*rule)))
+            .collect(),
+        ErrorVariant::CustomError { .. } => Vec::new(),
+    };
+    expected.sort();
+    expected.dedup();
+
+    // Running out of input where a command or a keyword closing a compound
+    // command is due means more input can complete it. A missing
+    // redirection target cannot be completed on the next line though.
+    let offset = match error.location {
+        InputLocation::Pos(offset) | InputLocation::Span((offset, _)) => offset,
+    };
+    let at_end = input[offset..].trim().is_empty();
+    let incomplete = incomplete(input, comments) || (at_end && expected.iter().any(|rule| rule != "word"));
+
+    let source_line = input.lines().nth(line - 1).unwrap_or("");
+    ParseError::new(line, column, expected, source_line, incomplete)
+}
+
+/// Names `rule` for use in error messages
+fn describe(rule: Rule) -> &'static str {
+    match rule {
+        Rule::word | Rule::name => "word",
+        Rule::fd => "file descriptor",
+        Rule::and_or_op => "operator",
+        Rule::redirect
+            | Rule::redirect_std_write
+            | Rule::redirect_std_append
+            | Rule::redirect_move_out
+            | Rule::redirect_move_in
+            | Rule::redirect_close_out
+            | Rule::redirect_close_in
+            | Rule::redirect_duplicate_out
+            | Rule::redirect_duplicate_in
+            | Rule::redirect_rw
+            | Rule::redirect_append
+            | Rule::redirect_clobber
+            | Rule::redirect_write
+            | Rule::redirect_read => "redirection",
+        Rule::assign | Rule::simple_command | Rule::command | Rule::pipeline | Rule::and_or
+            | Rule::list | Rule::compound_command | Rule::function_def => "command",
+        Rule::kw_then => "then",
+        Rule::kw_elif => "elif",
+        Rule::kw_else => "else",
+        Rule::kw_fi => "fi",
+        Rule::kw_do => "do",
+        Rule::kw_done => "done",
+        Rule::kw_in => "in",
+        Rule::kw_rbrace => "}",
+        Rule::kw_dbracket_end => "]]",
+        Rule::cond_unary_op | Rule::cond_binary_op | Rule::cond_regex_op => "operator",
+        Rule::regex => "regular expression",
+        Rule::cond_or | Rule::cond_and | Rule::cond_not | Rule::cond_group
+            | Rule::cond_unary | Rule::cond_binary | Rule::cond_regex => "expression",
+        Rule::array_end => ")",
+        Rule::EOI => "end of input",
+        _ => "token",
+    }
+}
+
+/// Checks whether `input` ends inside an unfinished construct
+///
+/// That is the case for an unclosed quote, a trailing backslash and input
+/// ending with a pipe or `&&` and `||`, all of which need another line.
+/// Quotes in comments do not count when `comments` is true.
+fn incomplete(input: &str, comments: bool) -> bool {
+    let mut chars = input.chars();
+    let mut quote = None;
+    let mut last = None;
+    let mut prev = None;
+    while let Some(c) = chars.next() {
+        let word_start = prev.is_none_or(|prev: char| prev.is_whitespace() || ";|&<>()".contains(prev));
+        prev = Some(c);
+        match (quote, c) {
+            (None, '#') if comments && word_start => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        prev = Some(c);
+                        break;
+                    }
+                }
+                continue;
             },
-            Rule::EOI => (),
-            _ => unreachable!(),
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => (),
+            (_, '\\') => match chars.next() {
+                None => return true,
+                Some('\n') if chars.as_str().is_empty() => return true,
+                _ => (),
+            },
+            (Some(_), _) => (),
+            (None, '\'' | '"') => quote = Some(c),
+            _ => (),
+        }
+        if quote.is_none() && !c.is_whitespace() {
+            last = Some(c);
         }
     }
 
-    sequence
+    quote.is_some()
+        || last == Some('|')
+        || (last == Some('&') && input.trim_end().ends_with("&&"))
+}
+
+fn span(pair: &Pair<Rule>) -> Span {
+    let span = pair.as_span();
+    Span::new(span.start(), span.end())
+}
+
+/// Iterates over inner pairs of `pair`, skipping keywords
+fn inner(pair: Pair<'_, Rule>) -> impl Iterator<Item = Pair<'_, Rule>> {
+    pair.into_inner().filter(|pair| !matches!(pair.as_rule(),
+        Rule::kw_if | Rule::kw_then | Rule::kw_elif | Rule::kw_else | Rule::kw_fi |
+        Rule::kw_while | Rule::kw_until | Rule::kw_for | Rule::kw_in |
+        Rule::kw_do | Rule::kw_done | Rule::kw_lbrace | Rule::kw_rbrace |
+        Rule::kw_dbracket | Rule::kw_dbracket_end
+    ))
+}
+
+fn parse_list<'a>(list: Pair<'a, Rule>) -> List<'a> {
+    assert!(list.as_rule() == Rule::list);
+    let span = span(&list);
+    List {
+        items: list.into_inner().map(parse_and_or).collect(),
+        span,
+    }
+}
+
+fn parse_and_or<'a>(and_or: Pair<'a, Rule>) -> AndOr<'a> {
+    assert!(and_or.as_rule() == Rule::and_or);
+    let span = span(&and_or);
+    let mut pairs = and_or.into_inner();
+    let first = parse_pipeline(pairs.next().unwrap());
+    let mut rest = Vec::new();
+    while let (Some(op), Some(pipeline)) = (pairs.next(), pairs.next()) {
+        let kind = match op.as_str() {
+            "&&" => AndOrKind::And,
+            "||" => AndOrKind::Or,
+            _ => unreachable!(),
+        };
+        rest.push((kind, parse_pipeline(pipeline)));
+    }
+    AndOr { first, rest, span }
+}
+
+fn parse_pipeline<'a>(pipeline: Pair<'a, Rule>) -> Pipeline<'a> {
+    assert!(pipeline.as_rule() == Rule::pipeline);
+    let span = span(&pipeline);
+    let mut negated = false;
+    let mut commands = Vec::new();
+    for pair in pipeline.into_inner() {
+        match pair.as_rule() {
+            Rule::bang => negated = true,
+            Rule::command => commands.push(parse_command(pair)),
+            // As if `2>&1` followed the redirections of the command.
+            Rule::pipe_stderr => commands.last_mut().unwrap().redirects.push(Redirect::FdDup(1, 2)),
+            _ => unreachable!(),
+        }
+    }
+    Pipeline { negated, commands, span }
 }
 
-fn parse_command(command: Pair<Rule>) -> Command {
+fn parse_command<'a>(command: Pair<'a, Rule>) -> Command<'a> {
     assert!(command.as_rule() == Rule::command);
-    let command = command.into_inner().next().unwrap();
-    match command.as_rule() {
-        Rule::execute => {
-            let atoms: Vec<_> = command.into_inner()
-                .map(|pair| {
-                    let span = pair.as_span();
-                    let kind = match pair.as_rule() {
-                        Rule::redirect => parse_redirect(pair),
-                        Rule::word => AtomKind::Word(String::from(span.as_str())),
-                        Rule::pipe => AtomKind::Pipe,
-                        _ => unreachable!(),
-                    };
-                    Atom::new(kind, span.start(), span.end())
-                })
-                .collect();
-            Command::new(CommandKind::Execute, atoms)
-        },
-        Rule::assign => {
-            let atoms: Vec<_> = command.into_inner()
-                .map(|pair| pair.as_span())
-                .map(|span| {
-                    Atom::new(
-                        AtomKind::Word(String::from(span.as_str())),
-                        span.start(),
-                        span.end()
-                    )
+    let span = span(&command);
+    let line = command.as_span().start_pos().line_col().0;
+    let mut pairs = command.into_inner();
+    let first = pairs.next().unwrap();
+    match first.as_rule() {
+        Rule::simple_command => {
+            let mut simple = SimpleCommand { assigns: Vec::new(), words: Vec::new() };
+            let mut redirects = Vec::new();
+            for pair in first.into_inner() {
+                match pair.as_rule() {
+                    Rule::assign => simple.assigns.push(parse_assign(pair)),
+                    Rule::redirect => redirects.push(parse_redirect(pair)),
+                    Rule::word => simple.words.push(Cow::Borrowed(pair.as_str())),
+                    _ => unreachable!(),
+                }
+            }
+            Command { kind: CommandKind::Simple(simple), redirects, span, line }
+        },
+        Rule::compound_command => Command {
+            kind: CommandKind::Compound(parse_compound(first)),
+            redirects: pairs.map(parse_redirect).collect(),
+            span,
+            line,
+        },
+        Rule::function_def => {
+            let mut pairs = first.into_inner();
+            let name = Cow::Borrowed(pairs.next().unwrap().as_str());
+            let body = pairs.next().unwrap();
+            let body = Command {
+                span: self::span(&body),
+                kind: CommandKind::Compound(parse_compound(body)),
+                redirects: pairs.map(parse_redirect).collect(),
+                line,
+            };
+            Command { kind: CommandKind::FunctionDef(name, Box::new(body)), redirects: Vec::new(), span, line }
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn parse_assign<'a>(assign: Pair<'a, Rule>) -> Assign<'a> {
+    let mut parts = assign.into_inner();
+    let name = Cow::Borrowed(parts.next().unwrap().as_str());
+    let mut subscript = None;
+    let mut append = false;
+    let mut value = AssignValue::Word(Cow::Borrowed(""));
+    for part in parts {
+        match part.as_rule() {
+            Rule::subscript => subscript = Some(Cow::Borrowed(part.as_str())),
+            Rule::assign_op => append = part.as_str() == "+=",
+            Rule::word => value = AssignValue::Word(Cow::Borrowed(part.as_str())),
+            Rule::array => value = AssignValue::Array(part.into_inner()
+                .filter(|element| element.as_rule() == Rule::array_element)
+                .map(|element| {
+                    let mut parts = element.into_inner();
+                    match parts.next() {
+                        Some(key) if key.as_rule() == Rule::subscript => {
+                            let value = parts.next().map_or("", |value| value.as_str());
+                            (Some(Cow::Borrowed(key.as_str())), Cow::Borrowed(value))
+                        },
+                        Some(word) => (None, Cow::Borrowed(word.as_str())),
+                        None => unreachable!(),
+                    }
                 })
-                .collect();
-            Command::new(CommandKind::Assign, atoms)
+                .collect()),
+            _ => unreachable!(),
+        }
+    }
+    Assign { name, subscript, append, value }
+}
+
+fn parse_compound<'a>(compound: Pair<'a, Rule>) -> CompoundCommand<'a> {
+    assert!(compound.as_rule() == Rule::compound_command);
+    let compound = compound.into_inner().next().unwrap();
+    let rule = compound.as_rule();
+    let mut pairs = inner(compound);
+    match rule {
+        Rule::brace_group => CompoundCommand::Brace(parse_list(pairs.next().unwrap())),
+        Rule::subshell => CompoundCommand::Subshell(parse_list(pairs.next().unwrap())),
+        Rule::if_clause => {
+            let lists: Vec<_> = pairs.map(parse_list).collect();
+            let mut branches = Vec::new();
+            let mut lists = lists.into_iter();
+            let mut otherwise = None;
+            while let Some(condition) = lists.next() {
+                match lists.next() {
+                    Some(body) => branches.push((condition, body)),
+                    None => otherwise = Some(condition),
+                }
+            }
+            CompoundCommand::If { branches, otherwise }
+        },
+        Rule::while_clause => {
+            let condition = parse_list(pairs.next().unwrap());
+            CompoundCommand::While(condition, parse_list(pairs.next().unwrap()))
+        },
+        Rule::until_clause => {
+            let condition = parse_list(pairs.next().unwrap());
+            CompoundCommand::Until(condition, parse_list(pairs.next().unwrap()))
+        },
+        Rule::for_clause => {
+            let name = Cow::Borrowed(pairs.next().unwrap().as_str());
+            let mut words = None;
+            let mut body = List::new();
+            for pair in pairs {
+                match pair.as_rule() {
+                    Rule::for_words => words = Some(inner(pair)
+                        .map(|word| Cow::Borrowed(word.as_str()))
+                        .collect()),
+                    Rule::list => body = parse_list(pair),
+                    _ => unreachable!(),
+                }
+            }
+            CompoundCommand::For(name, words, body)
+        },
+        Rule::conditional => CompoundCommand::Conditional(parse_condition(pairs.next().unwrap())),
+        _ => unreachable!(),
+    }
+}
+
+fn parse_condition<'a>(condition: Pair<'a, Rule>) -> Condition<'a> {
+    let rule = condition.as_rule();
+    let text = condition.as_str();
+    let mut pairs = condition.into_inner();
+    let mut next = || Cow::Borrowed(pairs.next().unwrap().as_str());
+    match rule {
+        Rule::word => Condition::Word(Cow::Borrowed(text)),
+        Rule::cond_unary => Condition::Unary(next(), next()),
+        Rule::cond_binary | Rule::cond_regex => Condition::Binary(next(), next(), next()),
+        Rule::cond_group => parse_condition(pairs.next().unwrap()),
+        Rule::cond_not => Condition::Not(Box::new(parse_condition(pairs.nth(1).unwrap()))),
+        Rule::cond_and | Rule::cond_or => {
+            let first = parse_condition(pairs.next().unwrap());
+            pairs.map(parse_condition).fold(first, |lhs, rhs| match rule {
+                Rule::cond_and => Condition::And(Box::new(lhs), Box::new(rhs)),
+                _ => Condition::Or(Box::new(lhs), Box::new(rhs)),
+            })
         },
         _ => unreachable!(),
     }
 }
 
-fn parse_redirect(redirect: Pair<Rule>) -> AtomKind {
+fn parse_redirect<'a>(redirect: Pair<'a, Rule>) -> Redirect<'a> {
     let redirect = redirect.into_inner().next().unwrap();
     match redirect.as_rule() {
         Rule::redirect_move_in => {
             let mut params = redirect.into_inner().rev();
             let src = params.next().unwrap().as_str().parse().unwrap();
             let dst = params.next().as_ref().map(Pair::as_str).unwrap_or("0").parse().unwrap();
-            AtomKind::FdMov(src, dst)
+            Redirect::FdMov(src, dst)
         },
         Rule::redirect_move_out => {
             let mut params = redirect.into_inner().rev();
             let src = params.next().unwrap().as_str().parse().unwrap();
             let dst = params.next().as_ref().map(Pair::as_str).unwrap_or("1").parse().unwrap();
-            AtomKind::FdMov(src, dst)
+            Redirect::FdMov(src, dst)
+        },
+        Rule::redirect_close_in => {
+            let fd = redirect.into_inner().next().as_ref().map(Pair::as_str).unwrap_or("0").parse().unwrap();
+            Redirect::FdClose(fd)
+        },
+        Rule::redirect_close_out => {
+            let fd = redirect.into_inner().next().as_ref().map(Pair::as_str).unwrap_or("1").parse().unwrap();
+            Redirect::FdClose(fd)
         },
         Rule::redirect_duplicate_in => {
             let mut params = redirect.into_inner().rev();
             let src = params.next().unwrap().as_str().parse().unwrap();
             let dst = params.next().as_ref().map(Pair::as_str).unwrap_or("0").parse().unwrap();
-            AtomKind::FdDup(src, dst)
+            Redirect::FdDup(src, dst)
         },
         Rule::redirect_duplicate_out => {
             let mut params = redirect.into_inner().rev();
             let src = params.next().unwrap().as_str().parse().unwrap();
             let dst = params.next().as_ref().map(Pair::as_str).unwrap_or("1").parse().unwrap();
-            AtomKind::FdDup(src, dst)
+            Redirect::FdDup(src, dst)
         },
         Rule::redirect_write => {
             let mut params = redirect.into_inner().rev();
-            let file = String::from(params.next().unwrap().as_str());
+            let file = Cow::Borrowed(params.next().unwrap().as_str());
+            let fd = params.next().as_ref().map(Pair::as_str).unwrap_or("1").parse().unwrap();
+            Redirect::FileWrite(file, fd)
+        },
+        Rule::redirect_clobber => {
+            let mut params = redirect.into_inner().rev();
+            let file = Cow::Borrowed(params.next().unwrap().as_str());
             let fd = params.next().as_ref().map(Pair::as_str).unwrap_or("1").parse().unwrap();
-            AtomKind::FileWrite(file, fd)
+            Redirect::FileClobber(file, fd)
         },
         Rule::redirect_read => {
             let mut params = redirect.into_inner().rev();
-            let file = String::from(params.next().unwrap().as_str());
+            let file = Cow::Borrowed(params.next().unwrap().as_str());
             let fd = params.next().as_ref().map(Pair::as_str).unwrap_or("0").parse().unwrap();
-            AtomKind::FileRead(file, fd)
+            Redirect::FileRead(file, fd)
         },
         Rule::redirect_append => {
             let mut params = redirect.into_inner().rev();
-            let file = String::from(params.next().unwrap().as_str());
+            let file = Cow::Borrowed(params.next().unwrap().as_str());
             let fd = params.next().as_ref().map(Pair::as_str).unwrap_or("1").parse().unwrap();
-            AtomKind::FileAppend(file, fd)
+            Redirect::FileAppend(file, fd)
         },
         Rule::redirect_rw => {
             let mut params = redirect.into_inner().rev();
-            let file = String::from(params.next().unwrap().as_str());
+            let file = Cow::Borrowed(params.next().unwrap().as_str());
             let fd = params.next().as_ref().map(Pair::as_str).unwrap_or("0").parse().unwrap();
-            AtomKind::FileRW(file, fd)
+            Redirect::FileRW(file, fd)
         },
         Rule::redirect_std_write => {
-            let file = String::from(redirect.into_inner().next().unwrap().as_str());
-            AtomKind::StdWrite(file)
+            let file = Cow::Borrowed(redirect.into_inner().next().unwrap().as_str());
+            Redirect::StdWrite(file)
         },
         Rule::redirect_std_append => {
-            let file = String::from(redirect.into_inner().next().unwrap().as_str());
-            AtomKind::StdAppend(file)
+            let file = Cow::Borrowed(redirect.into_inner().next().unwrap().as_str());
+            Redirect::StdAppend(file)
         },
         _ => unreachable!()
     }
 }
-
-fn parse_separator(separator: Pair<Rule>) -> SequenceKind {
-    assert!(separator.as_rule() == Rule::separator);
-    match separator.as_str() {
-        ";" => SequenceKind::Seq,
-        "||" => SequenceKind::Or,
-        "&&" => SequenceKind::And,
-        _ => unreachable!(),
-    }
-}
\ No newline at end of file
@@ -1,86 +1,322 @@
 pub mod bash;
 
+use std::borrow::Cow;
+use std::fmt;
 use std::os::unix::io::RawFd;
 
+/// Byte range of a node in the parsed input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+/// Text taken from the parsed input
+///
+/// Words borrow from the input where possible. Nodes that outlive the input,
+/// such as function bodies, own their text after `into_owned`.
+pub type Text<'a> = Cow<'a, str>;
+
+fn owned(text: Text) -> Text<'static> {
+    Cow::Owned(text.into_owned())
+}
+
+fn owned_all(texts: Vec<Text>) -> Vec<Text<'static>> {
+    texts.into_iter().map(owned).collect()
+}
+
 #[derive(Clone, Debug)]
-pub enum AtomKind {
-    Word(String),
-    Pipe,
+pub enum Redirect<'a> {
     FdDup(RawFd, RawFd),
     FdMov(RawFd, RawFd),
-    FileRead(String, RawFd),
-    FileWrite(String, RawFd),
-    FileAppend(String, RawFd),
-    FileRW(String, RawFd),
-    StdWrite(String),
-    StdAppend(String),
+    FdClose(RawFd),
+    FileRead(Text<'a>, RawFd),
+    FileWrite(Text<'a>, RawFd),
+    FileClobber(Text<'a>, RawFd),
+    FileAppend(Text<'a>, RawFd),
+    FileRW(Text<'a>, RawFd),
+    StdWrite(Text<'a>),
+    StdAppend(Text<'a>),
+}
+
+impl<'a> Redirect<'a> {
+    pub fn into_owned(self) -> Redirect<'static> {
+        match self {
+            Redirect::FdDup(src, dst) => Redirect::FdDup(src, dst),
+            Redirect::FdMov(src, dst) => Redirect::FdMov(src, dst),
+            Redirect::FdClose(fd) => Redirect::FdClose(fd),
+            Redirect::FileRead(file, fd) => Redirect::FileRead(owned(file), fd),
+            Redirect::FileWrite(file, fd) => Redirect::FileWrite(owned(file), fd),
+            Redirect::FileClobber(file, fd) => Redirect::FileClobber(owned(file), fd),
+            Redirect::FileAppend(file, fd) => Redirect::FileAppend(owned(file), fd),
+            Redirect::FileRW(file, fd) => Redirect::FileRW(owned(file), fd),
+            Redirect::StdWrite(file) => Redirect::StdWrite(owned(file)),
+            Redirect::StdAppend(file) => Redirect::StdAppend(owned(file)),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
-pub struct Atom {
-    kind: AtomKind,
-    start: usize,
-    end: usize,
+pub enum AssignValue<'a> {
+    Word(Text<'a>),
+    /// Array elements, each with an optional subscript as in `([key]=value)`
+    Array(Vec<(Option<Text<'a>>, Text<'a>)>),
 }
 
-impl Atom {
-    pub fn new(kind: AtomKind, start: usize, end: usize) -> Atom {
-        Atom { kind, start, end }
-    }
+/// Assignment such as `a=1`, `a[i]+=x` or `a=(x y)`
+#[derive(Clone, Debug)]
+pub struct Assign<'a> {
+    pub name: Text<'a>,
+    pub subscript: Option<Text<'a>>,
+    /// Whether the value is appended, as with `+=`
+    pub append: bool,
+    pub value: AssignValue<'a>,
+}
 
-    pub fn kind(&self) -> AtomKind {
-        self.kind.clone()
+impl<'a> Assign<'a> {
+    pub fn into_owned(self) -> Assign<'static> {
+        Assign {
+            name: owned(self.name),
+            subscript: self.subscript.map(owned),
+            append: self.append,
+            value: match self.value {
+                AssignValue::Word(word) => AssignValue::Word(owned(word)),
+                AssignValue::Array(items) => AssignValue::Array(items.into_iter()
+                    .map(|(key, value)| (key.map(owned), owned(value)))
+                    .collect()),
+            },
+        }
     }
 }
 
+/// Command with words and assignments, such as `CC=gcc make -j4 > log`
 #[derive(Clone, Debug)]
-pub enum CommandKind {
-    Execute,
-    Assign,
+pub struct SimpleCommand<'a> {
+    pub assigns: Vec<Assign<'a>>,
+    pub words: Vec<Text<'a>>,
+}
+
+impl<'a> SimpleCommand<'a> {
+    pub fn into_owned(self) -> SimpleCommand<'static> {
+        SimpleCommand {
+            assigns: self.assigns.into_iter().map(Assign::into_owned).collect(),
+            words: owned_all(self.words),
+        }
+    }
 }
 
+/// Expression of a `[[ ... ]]` conditional command
 #[derive(Clone, Debug)]
-pub struct Command {
-    kind: CommandKind,
-    atoms: Vec<Atom>,
+pub enum Condition<'a> {
+    /// Word that holds if it is not empty
+    Word(Text<'a>),
+    /// Operator and operand, such as `-f file`
+    Unary(Text<'a>, Text<'a>),
+    /// Operands around an operator, such as `a == b` or `a =~ regex`
+    Binary(Text<'a>, Text<'a>, Text<'a>),
+    Not(Box<Condition<'a>>),
+    And(Box<Condition<'a>>, Box<Condition<'a>>),
+    Or(Box<Condition<'a>>, Box<Condition<'a>>),
 }
 
-impl Command {
-    pub fn new(kind: CommandKind, atoms: Vec<Atom>) -> Command {
-        Command { kind, atoms }    
+impl<'a> Condition<'a> {
+    pub fn into_owned(self) -> Condition<'static> {
+        let boxed = |condition: Box<Condition>| Box::new(condition.into_owned());
+        match self {
+            Condition::Word(word) => Condition::Word(owned(word)),
+            Condition::Unary(op, operand) => Condition::Unary(owned(op), owned(operand)),
+            Condition::Binary(lhs, op, rhs) => Condition::Binary(owned(lhs), owned(op), owned(rhs)),
+            Condition::Not(condition) => Condition::Not(boxed(condition)),
+            Condition::And(lhs, rhs) => Condition::And(boxed(lhs), boxed(rhs)),
+            Condition::Or(lhs, rhs) => Condition::Or(boxed(lhs), boxed(rhs)),
+        }
     }
+}
+
+#[derive(Clone, Debug)]
+pub enum CompoundCommand<'a> {
+    Brace(List<'a>),
+    Subshell(List<'a>),
+    If {
+        branches: Vec<(List<'a>, List<'a>)>,
+        otherwise: Option<List<'a>>,
+    },
+    While(List<'a>, List<'a>),
+    Until(List<'a>, List<'a>),
+    For(Text<'a>, Option<Vec<Text<'a>>>, List<'a>),
+    Conditional(Condition<'a>),
+}
 
-    pub fn kind(&self) -> CommandKind {
-        self.kind.clone()
+impl<'a> CompoundCommand<'a> {
+    pub fn into_owned(self) -> CompoundCommand<'static> {
+        match self {
+            CompoundCommand::Brace(list) => CompoundCommand::Brace(list.into_owned()),
+            CompoundCommand::Subshell(list) => CompoundCommand::Subshell(list.into_owned()),
+            CompoundCommand::If { branches, otherwise } => CompoundCommand::If {
+                branches: branches.into_iter()
+                    .map(|(condition, body)| (condition.into_owned(), body.into_owned()))
+                    .collect(),
+                otherwise: otherwise.map(List::into_owned),
+            },
+            CompoundCommand::While(condition, body) => CompoundCommand::While(condition.into_owned(), body.into_owned()),
+            CompoundCommand::Until(condition, body) => CompoundCommand::Until(condition.into_owned(), body.into_owned()),
+            CompoundCommand::For(name, words, body) => CompoundCommand::For(owned(name), words.map(owned_all), body.into_owned()),
+            CompoundCommand::Conditional(condition) => CompoundCommand::Conditional(condition.into_owned()),
+        }
     }
+}
+
+#[derive(Clone, Debug)]
+pub enum CommandKind<'a> {
+    Simple(SimpleCommand<'a>),
+    Compound(CompoundCommand<'a>),
+    FunctionDef(Text<'a>, Box<Command<'a>>),
+}
 
-    pub fn atoms(&self) -> Vec<Atom> {
-        self.atoms.clone()
+#[derive(Clone, Debug)]
+pub struct Command<'a> {
+    pub kind: CommandKind<'a>,
+    pub redirects: Vec<Redirect<'a>>,
+    pub span: Span,
+    /// Line the command starts on, counted from 1
+    pub line: usize,
+}
+
+impl<'a> Command<'a> {
+    pub fn into_owned(self) -> Command<'static> {
+        let kind = match self.kind {
+            CommandKind::Simple(simple) => CommandKind::Simple(simple.into_owned()),
+            CommandKind::Compound(compound) => CommandKind::Compound(compound.into_owned()),
+            CommandKind::FunctionDef(name, body) => CommandKind::FunctionDef(owned(name), Box::new(body.into_owned())),
+        };
+        Command {
+            kind,
+            redirects: self.redirects.into_iter().map(Redirect::into_owned).collect(),
+            span: self.span,
+            line: self.line,
+        }
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub enum SequenceKind {
-    Seq,
+/// Commands connected by pipes, optionally negated by `!`
+#[derive(Clone, Debug)]
+pub struct Pipeline<'a> {
+    pub negated: bool,
+    pub commands: Vec<Command<'a>>,
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AndOrKind {
     And,
-    Or
+    Or,
+}
+
+/// Pipelines connected by `&&` and `||`
+#[derive(Clone, Debug)]
+pub struct AndOr<'a> {
+    pub first: Pipeline<'a>,
+    pub rest: Vec<(AndOrKind, Pipeline<'a>)>,
+    pub span: Span,
 }
 
+/// Sequence of and-or lists separated by `;` or newlines
 #[derive(Clone, Debug)]
-pub struct Sequence {
-    commands: Vec<(SequenceKind, Command)>,
+pub struct List<'a> {
+    pub items: Vec<AndOr<'a>>,
+    pub span: Span,
 }
 
-impl Sequence {
-    pub fn new() -> Sequence {
-        Sequence { commands: Vec::new() }
+impl<'a> List<'a> {
+    pub fn new() -> List<'a> {
+        List { items: Vec::new(), span: Span::new(0, 0) }
     }
 
-    pub fn add(&mut self, seq: SequenceKind, command: Command) {
-        self.commands.push((seq, command))
+    pub fn into_owned(self) -> List<'static> {
+        let pipeline = |pipeline: Pipeline| Pipeline {
+            negated: pipeline.negated,
+            commands: pipeline.commands.into_iter().map(Command::into_owned).collect(),
+            span: pipeline.span,
+        };
+        List {
+            items: self.items.into_iter()
+                .map(|and_or| AndOr {
+                    first: pipeline(and_or.first),
+                    rest: and_or.rest.into_iter()
+                        .map(|(kind, rest)| (kind, pipeline(rest)))
+                        .collect(),
+                    span: and_or.span,
+                })
+                .collect(),
+            span: self.span,
+        }
     }
+}
 
-    pub fn get(self) -> Vec<(SequenceKind, Command)> {
-        self.commands
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    origin: Option<String>,
+    line: usize,
+    column: usize,
+    expected: Vec<String>,
+    source_line: String,
+    incomplete: bool,
+}
+
+impl ParseError {
+    pub fn new(line: usize, column: usize, expected: Vec<String>, source_line: &str, incomplete: bool) -> ParseError {
+        ParseError {
+            origin: None,
+            line,
+            column,
+            expected,
+            source_line: String::from(source_line),
+            incomplete,
+        }
+    }
+
+    /// Attributes the error to `origin`, a file name for instance, whose
+    /// parsed text started at line `first_line`
+    pub fn set_origin(&mut self, origin: &str, first_line: usize) {
+        self.origin = Some(String::from(origin));
+        self.line += first_line - 1;
+    }
+
+    /// Checks whether the input merely ended too early, so that more input
+    /// could complete it
+    pub fn incomplete(&self) -> bool {
+        self.incomplete
     }
-}
\ No newline at end of file
+
+    /// Renders the offending line with a caret under the error position
+    pub fn snippet(&self) -> String {
+        let indent: String = self.source_line.chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!("  {}\n  {}^", self.source_line, indent)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let origin = self.origin.as_deref().unwrap_or("rush");
+        write!(f, "{}:{}:{}: ", origin, self.line, self.column)?;
+        if self.incomplete {
+            write!(f, "Unexpected end of input.")?;
+        } else {
+            match self.expected.split_last() {
+                Some((last, [])) => write!(f, "Syntax error, expected {}.", last)?,
+                Some((last, rest)) => write!(f, "Syntax error, expected {} or {}.", rest.join(", "), last)?,
+                None => write!(f, "Syntax error.")?,
+            }
+        }
+        write!(f, "\n{}", self.snippet())
+    }
+}
@@ -1,13 +1,35 @@
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::os::unix::io::RawFd;
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use nix::sys::stat;
+use nix::sys::stat::{self, SFlag};
+use nix::unistd::{self, AccessFlags, Pid};
 use termion::event::{Event, Key};
 
+use super::builtin;
+use super::condition;
 use super::config::Config;
 use super::executor::{self, Executee, ExecuteeKind};
+use super::expand;
+use super::frecency::Frecency;
+use super::glob;
 use super::input::Input;
-use super::parser::{SequenceKind, CommandKind, Atom, AtomKind};
+use super::value::Value;
+use super::parser::{
+    bash, Assign, AssignValue, AndOr, AndOrKind, Command, CommandKind, CompoundCommand, Condition, List, Pipeline,
+    Redirect, SimpleCommand,
+};
+
+/// Options named by `set -o`, with the letters standing for them in `$-`
+pub const OPTIONS: &[(&str, char)] = &[
+    ("noclobber", 'C'),
+];
 
 pub enum Action {
     Process,
@@ -22,11 +44,48 @@ pub struct Shell {
     history_idx: usize,
     input: Input,
     prompt: String,
-    vars: HashMap<String, String>,
+    vars: HashMap<String, Value>,
+    exports: HashSet<String>,
+    dir_stack: Vec<String>,
+    frecency: Option<Frecency>,
+    arg0: String,
+    positional: Vec<String>,
+    functions: HashMap<String, Rc<Command<'static>>>,
+    aliases: BTreeMap<String, String>,
+    /// Descriptors above 2 opened by `exec`, which commands only inherit
+    /// when they redirect them
+    fds: BTreeSet<RawFd>,
+    /// Command run when the shell exits, set by `trap` for `EXIT`
+    exit_trap: Option<String>,
+    /// Status of the last pipeline, `$?`
+    status: usize,
+    /// Last argument of the last simple command, `$_`
+    last_arg: String,
+    /// Option flags the shell was started with or set since, `$-`
+    flags: String,
+    pid: Pid,
+    ppid: Pid,
+    shlvl: usize,
+    started: Instant,
+    random: Cell<u32>,
+    /// Line of the running command
+    lineno: usize,
+    /// Number of lines preceding the running source
+    line_base: usize,
+    /// File the running commands were read from
+    origin: Option<String>,
+    /// Assignments made by `${name:=word}`, applied once the expansion is
+    /// done
+    deferred: RefCell<Vec<(String, String)>>,
+    /// Whether an expansion failed, as `${name:?}` does for an unset name
+    expansion_failed: Cell<bool>,
+    /// Whether startup files are running, which failed expansions do not
+    /// end even in a non-interactive shell
+    starting: bool,
 }
 
 impl Shell {
-    pub fn new(config: Option<&Path>) -> Shell {
+    pub fn new(config: Option<&Path>, data: Option<&Path>) -> Shell {
         let config = config
             .map(|path| {
                 if let Some(config) = Config::load(path) {
@@ -36,38 +95,94 @@ impl Shell {
                     Config::default()
                 }
             })
-            .unwrap_or(Config::default());
+            .unwrap_or_default();
 
-        let vars = if config.respect_vars {
-            std::env::vars().collect()
+        let vars: HashMap<String, Value> = if config.respect_vars {
+            std::env::vars().map(|(name, value)| (name, Value::Scalar(value))).collect()
         } else {
             HashMap::new()
         };
+        let exports = vars.keys().cloned().collect();
 
         let mut bin_dirs = config.bin_dirs.clone();
 
         if config.respect_path {
-            if let Some(path) = vars.get("PATH") {
-                bin_dirs.extend(path.split(":").map(String::from).collect::<Vec<_>>());
+            if let Some(path) = vars.get("PATH").and_then(Value::scalar) {
+                bin_dirs.extend(path.split(':').map(String::from));
             }
         }
 
         let prompt = config.prompt.clone();
+        let aliases = config.aliases.clone();
+
+        let frecency = data
+            .filter(|_| config.frecency)
+            .map(|data| Frecency::new(&data.join("z")));
 
-        Shell {
+        let mut shell = Shell {
             config,
             bin_dirs,
             history: Vec::new(),
             history_idx: 1,
             input: Input::new(),
             prompt,
-            vars
+            vars,
+            exports,
+            dir_stack: Vec::new(),
+            frecency,
+            arg0: String::from("rush"),
+            positional: Vec::new(),
+            functions: HashMap::new(),
+            aliases,
+            fds: BTreeSet::new(),
+            exit_trap: None,
+            status: 0,
+            last_arg: String::new(),
+            flags: String::new(),
+            pid: unistd::getpid(),
+            ppid: unistd::getppid(),
+            shlvl: 0,
+            started: Instant::now(),
+            random: Cell::new(0),
+            lineno: 0,
+            line_base: 0,
+            origin: None,
+            deferred: RefCell::new(Vec::new()),
+            expansion_failed: Cell::new(false),
+            starting: false,
+        };
+        shell.shlvl = shell.var("SHLVL").and_then(|level| level.parse().ok()).unwrap_or(0) + 1;
+        shell.export("SHLVL");
+        shell.random.set(now().subsec_nanos() ^ shell.pid.as_raw() as u32);
+        shell.init_pwd();
+        shell
+    }
+
+    /// Makes `PWD` name the current directory
+    ///
+    /// An inherited `PWD` is kept only if it still leads to where we are, so
+    /// that logical paths through symlinks survive.
+    fn init_pwd(&mut self) {
+        let cwd = match unistd::getcwd() {
+            Ok(cwd) => cwd,
+            Err(_) => return,
+        };
+        let inherited = self.var("PWD")
+            .filter(|pwd| pwd.starts_with('/'))
+            .filter(|pwd| Path::new(pwd).canonicalize().ok().as_ref() == Some(&cwd));
+        if inherited.is_none() {
+            self.set_var("PWD", cwd.to_string_lossy().into_owned());
         }
+        self.export("PWD");
     }
 
     pub fn event(&mut self, event: &Event) -> Option<Action> {
         match event {
             Event::Key(key) => match key {
+                Key::Char('\n') if self.input.incomplete(self.config.interactive_comments) => {
+                    self.input.push('\n');
+                    None
+                },
                 Key::Char('\n') => Some(Action::Process),
                 Key::Char(c) => {
                     self.input.push(*c);
@@ -94,13 +209,19 @@ impl Shell {
                 },
                 Key::Ctrl('l') => Some(Action::ClearScreen),
                 Key::Up => {
-                    if self.history_idx > 0 && self.history.len() > 0 {
+                    if self.input.up() {
+                        return None;
+                    }
+                    if self.history_idx > 0 && !self.history.is_empty() {
                         self.history_idx -= 1;
                         self.input.set(&self.history[self.history_idx]);
                     }
                     None
                 },
                 Key::Down => {
+                    if self.input.down() {
+                        return None;
+                    }
                     if self.history_idx + 1 < self.history.len() {
                         self.history_idx += 1;
                         self.input.set(&self.history[self.history_idx]);
@@ -124,10 +245,284 @@ impl Shell {
         }
     }
 
-    pub fn vars(&self) -> &HashMap<String, String> {
+    pub fn vars(&self) -> &HashMap<String, Value> {
         &self.vars
     }
 
+    pub fn var(&self, name: &str) -> Option<&String> {
+        self.vars.get(name).and_then(Value::scalar)
+    }
+
+    /// Sets variable `name` to `value`, or its element 0 if it is an array
+    pub fn set_var(&mut self, name: &str, value: String) {
+        match self.vars.get_mut(name) {
+            Some(Value::Scalar(_)) | None => {
+                self.vars.insert(String::from(name), Value::Scalar(value));
+            },
+            Some(array) => {
+                array.set("0", value);
+            },
+        }
+    }
+
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.vars.get(name)
+    }
+
+    pub fn set_value(&mut self, name: &str, value: Value) {
+        self.vars.insert(String::from(name), value);
+    }
+
+    /// Declares `name` as an array of the same kind as `value`, keeping the
+    /// elements it has if it already is one
+    pub fn declare_array(&mut self, name: &str, value: Value) {
+        let kept = matches!(
+            (self.vars.get(name), &value),
+            (Some(Value::Indexed(_)), Value::Indexed(_)) | (Some(Value::Associative(_)), Value::Associative(_))
+        );
+        if !kept {
+            let old = self.vars.insert(String::from(name), value);
+            if let (Some(Value::Scalar(old)), Some(array)) = (old, self.vars.get_mut(name)) {
+                array.set("0", old);
+            }
+        }
+    }
+
+    /// Performs `assign`, expanding its value
+    ///
+    /// Unkeyed elements of an array value are split into fields and numbered
+    /// on from the last key, with `+=` from the end of the existing array.
+    /// Associative arrays take unkeyed elements as alternating keys and
+    /// values.
+    pub fn assign(&mut self, assign: &Assign) {
+        let name = assign.name.as_ref();
+        match (&assign.value, &assign.subscript) {
+            (AssignValue::Word(word), None) => {
+                let mut value = expand::string(self, word);
+                if assign.append {
+                    value = self.var(name).cloned().unwrap_or_default() + &value;
+                }
+                self.set_var(name, value);
+            },
+            (AssignValue::Word(word), Some(subscript)) => {
+                let key = self.subscript(name, subscript);
+                let mut value = expand::string(self, word);
+                let array = self.vars.entry(String::from(name))
+                    .or_insert_with(|| Value::Indexed(Default::default()));
+                if assign.append {
+                    value = array.get(&key).cloned().unwrap_or_default() + &value;
+                }
+                if !array.set(&key, value) {
+                    error!("rush: {}[{}]: Bad array subscript.", name, key);
+                }
+            },
+            (AssignValue::Array(items), _) => {
+                let mut array = match self.vars.get(name) {
+                    Some(Value::Associative(items)) if assign.append => Value::Associative(items.clone()),
+                    Some(Value::Associative(_)) => Value::Associative(Default::default()),
+                    Some(value) if assign.append => {
+                        let mut array = Value::Indexed(Default::default());
+                        for (key, value) in value.keys().into_iter().zip(value.values()) {
+                            array.set(&key, value);
+                        }
+                        array
+                    },
+                    _ => Value::Indexed(Default::default()),
+                };
+                let mut next = array.next_index();
+                let mut pending: Option<String> = None;
+                for (key, value) in items {
+                    if let Some(key) = key {
+                        let key = self.subscript_of(&array, key);
+                        let value = expand::string(self, value);
+                        next = key.parse::<usize>().map_or(next, |key| key + 1);
+                        array.set(&key, value);
+                        continue;
+                    }
+                    for value in expand::word(self, value) {
+                        match (&array, pending.take()) {
+                            (Value::Associative(_), None) => pending = Some(value),
+                            (Value::Associative(_), Some(key)) => {
+                                array.set(&key, value);
+                            },
+                            _ => {
+                                array.set(&next.to_string(), value);
+                                next += 1;
+                            },
+                        }
+                    }
+                }
+                if let Some(key) = pending {
+                    array.set(&key, String::new());
+                }
+                self.vars.insert(String::from(name), array);
+            },
+        }
+    }
+
+    /// Expands `subscript` of variable `name` into a key
+    pub fn subscript(&self, name: &str, subscript: &str) -> String {
+        match self.vars.get(name) {
+            Some(value) => self.subscript_of(value, subscript),
+            None => self.subscript_of(&Value::Indexed(Default::default()), subscript),
+        }
+    }
+
+    /// Expands `subscript` into a key of `array`
+    ///
+    /// Associative arrays take the expanded string as it is. Indexed arrays
+    /// take a number, or the value of a variable named by the subscript.
+    fn subscript_of(&self, array: &Value, subscript: &str) -> String {
+        let key = expand::string(self, subscript);
+        if let Value::Associative(_) = array {
+            return key;
+        }
+        let key = key.trim();
+        if key.parse::<i64>().is_ok() {
+            String::from(key)
+        } else {
+            self.param(key).filter(|value| value.trim().parse::<i64>().is_ok())
+                .unwrap_or_else(|| String::from("0"))
+        }
+    }
+
+    pub fn export(&mut self, name: &str) {
+        self.exports.insert(String::from(name));
+    }
+
+    pub fn exports(&self) -> &HashSet<String> {
+        &self.exports
+    }
+
+    /// Removes variable `name`, along with its export
+    pub fn unset_var(&mut self, name: &str) {
+        self.vars.remove(name);
+        self.exports.remove(name);
+    }
+
+    pub fn unset_function(&mut self, name: &str) {
+        self.functions.remove(name);
+    }
+
+    /// Returns the value of parameter `name`
+    ///
+    /// Besides variables, this covers positional and special parameters and
+    /// dynamic variables, which are computed on every read.
+    pub fn param(&self, name: &str) -> Option<String> {
+        match name {
+            "0" => Some(self.arg0.clone()),
+            "#" => Some(self.positional.len().to_string()),
+            "?" => Some(self.status.to_string()),
+            "$" => Some(self.pid.to_string()),
+            "-" => Some(self.flags.clone()),
+            "_" => Some(self.last_arg.clone()),
+            // There are no background jobs, so `$!` is never set.
+            "!" => None,
+            "RANDOM" => Some(self.random().to_string()),
+            "SECONDS" => Some(self.started.elapsed().as_secs().to_string()),
+            "LINENO" => Some(self.lineno.to_string()),
+            "EPOCHSECONDS" => Some(now().as_secs().to_string()),
+            "EPOCHREALTIME" => {
+                let now = now();
+                Some(format!("{}.{:06}", now.as_secs(), now.subsec_micros()))
+            },
+            "PPID" => Some(self.ppid.to_string()),
+            "BASHPID" => Some(unistd::getpid().to_string()),
+            "SHLVL" => Some(self.shlvl.to_string()),
+            "*" => {
+                let separator: String = expand::ifs(self).chars().take(1).collect();
+                Some(self.positional.join(&separator))
+            },
+            name if name.chars().all(|c| c.is_ascii_digit()) => {
+                let n: usize = name.parse().ok()?;
+                // Leading zeros still name `$0`, as `${00}` does.
+                if n == 0 {
+                    return Some(self.arg0.clone());
+                }
+                n.checked_sub(1).and_then(|i| self.positional.get(i)).cloned()
+            },
+            name => {
+                let deferred = self.deferred.borrow();
+                match deferred.iter().rev().find(|assign| assign.0 == name) {
+                    Some((_, value)) => Some(value.clone()),
+                    None => self.var(name).cloned(),
+                }
+            },
+        }
+    }
+
+    /// Assigns `value` to `name` once the running expansion is done
+    pub fn defer_assign(&self, name: &str, value: String) {
+        self.deferred.borrow_mut().push((String::from(name), value));
+    }
+
+    pub fn fail_expansion(&self) {
+        self.expansion_failed.set(true);
+    }
+
+    /// Applies assignments deferred during expansion
+    ///
+    /// Returns `false` if an expansion failed, which also ends a
+    /// non-interactive shell once it is done with its startup files.
+    fn finish_expansion(&mut self) -> bool {
+        for (name, value) in self.deferred.take() {
+            self.set_var(&name, value);
+        }
+        if !self.expansion_failed.replace(false) {
+            return true;
+        }
+        if !self.interactive() && !self.starting {
+            self.exit(1);
+        }
+        false
+    }
+
+    /// Returns the next value of `$RANDOM`, from 0 to 32767
+    fn random(&self) -> u32 {
+        let seed = self.random.get().wrapping_mul(1103515245).wrapping_add(12345);
+        self.random.set(seed);
+        (seed >> 16) & 0x7fff
+    }
+
+    pub fn set_flags(&mut self, flags: &str) {
+        self.flags = String::from(flags);
+    }
+
+    pub fn set_starting(&mut self, starting: bool) {
+        self.starting = starting;
+    }
+
+    /// Checks whether option `flag` is on
+    pub fn flag(&self, flag: char) -> bool {
+        self.flags.contains(flag)
+    }
+
+    /// Turns option `flag` on or off
+    pub fn set_flag(&mut self, flag: char, on: bool) {
+        if !on {
+            self.flags.retain(|c| c != flag);
+        } else if !self.flag(flag) {
+            self.flags.push(flag);
+        }
+    }
+
+    pub fn set_arg0(&mut self, arg0: &str) {
+        self.arg0 = String::from(arg0);
+    }
+
+    pub fn arg0(&self) -> &str {
+        &self.arg0
+    }
+
+    pub fn positional(&self) -> &Vec<String> {
+        &self.positional
+    }
+
+    /// Replaces the positional parameters, returning the previous ones
+    pub fn set_positional(&mut self, positional: Vec<String>) -> Vec<String> {
+        std::mem::replace(&mut self.positional, positional)
+    }
+
     pub fn bin_dirs(&self) -> &Vec<String> {
         &self.bin_dirs
     }
@@ -136,103 +531,553 @@ impl Shell {
         &self.config
     }
 
+    pub fn dir_stack(&self) -> &Vec<String> {
+        &self.dir_stack
+    }
+
+    pub fn dir_stack_mut(&mut self) -> &mut Vec<String> {
+        &mut self.dir_stack
+    }
+
+    pub fn aliases(&self) -> &BTreeMap<String, String> {
+        &self.aliases
+    }
+
+    pub fn aliases_mut(&mut self) -> &mut BTreeMap<String, String> {
+        &mut self.aliases
+    }
+
+    pub fn fds(&self) -> &BTreeSet<RawFd> {
+        &self.fds
+    }
+
+    pub fn fds_mut(&mut self) -> &mut BTreeSet<RawFd> {
+        &mut self.fds
+    }
+
+    pub fn exit_trap(&self) -> Option<&String> {
+        self.exit_trap.as_ref()
+    }
+
+    pub fn set_exit_trap(&mut self, action: Option<String>) {
+        self.exit_trap = action;
+    }
+
+    /// Ends the shell with `status`, running the `EXIT` trap first
+    ///
+    /// The trap sees `status` in `$?` and may exit with another one itself.
+    pub fn exit(&mut self, status: u8) -> ! {
+        if let Some(action) = self.exit_trap.take() {
+            self.status = status as usize;
+            self.run_source(&action, "trap", 1);
+        }
+        std::io::stdout().flush().unwrap_or(());
+        std::process::exit(status as i32);
+    }
+
+    /// Returns the status of the last pipeline, `$?`
+    pub fn status(&self) -> usize {
+        self.status
+    }
+
+    /// Checks whether the shell reads commands interactively
+    pub fn interactive(&self) -> bool {
+        self.flag('i')
+    }
+
+    pub fn frecency(&self) -> Option<&Frecency> {
+        self.frecency.as_ref()
+    }
+
+    /// Runs the input line, a command typed in or given with `-c`
+    ///
+    /// `interactive_comments` only applies to commands typed in; others
+    /// always have comments, as scripts do.
     pub fn process(&mut self) -> usize {
-        let sequence = self.input.get().get();
-        self.history.push(self.input.raw());
-        self.history_idx = self.history.len();
-        self.input.clear();
+        let raw = self.input.take();
+        let comments = self.config.interactive_comments || !self.interactive();
+        let source = self.expand_aliases(&raw, comments);
+        match bash::parse(&source, comments) {
+            Ok(list) => {
+                self.line_base = 0;
+                let retcode = self.run_list(&list);
+                self.history.push(raw);
+                self.history_idx = self.history.len();
+                retcode
+            },
+            Err(error) => {
+                eprintln!("{}", error);
+                self.status = 2;
+                2
+            },
+        }
+    }
 
+    /// Runs commands from file at `path`
+    ///
+    /// A `#!` line at the top is a comment like any other. Returns `None` if
+    /// the file cannot be read.
+    pub fn run_file(&mut self, path: &Path) -> Option<usize> {
+        let source = fs::read_to_string(path).ok()?;
+        let path = path.to_string_lossy();
+        let origin = self.origin.replace(path.to_string());
+        let retcode = self.run_source(&source, &path, 1);
+        self.origin = origin;
+        Some(retcode)
+    }
+
+    /// Returns where the running command was read from, as `file:line: `,
+    /// or nothing if it was not read from a file
+    pub fn location(&self) -> String {
+        match &self.origin {
+            Some(origin) => format!("{}:{}: ", origin, self.lineno),
+            None => String::new(),
+        }
+    }
+
+    /// Parses and runs `source` one complete command at a time
+    ///
+    /// Lines are collected until they form a complete construct, which runs
+    /// before the next line is looked at. A syntax error only skips the
+    /// command it is in, reported against `origin`, with line numbers
+    /// counted from `first_line`.
+    pub fn run_source(&mut self, source: &str, origin: &str, first_line: usize) -> usize {
         let mut retcode = 0;
-        for command in sequence {
-            match command.0 {
-                SequenceKind::And if retcode != 0 => continue,
-                SequenceKind::Or if retcode == 0 => continue,
-                _ => (),
+        let mut start = 0;
+        let mut line = first_line;
+        for (end, _) in source.match_indices('\n') {
+            let command = &source[start..=end];
+            if bash::parse(command, true).err().is_some_and(|error| error.incomplete()) {
+                continue;
             }
-            retcode = match command.1.kind() {
-                CommandKind::Execute => self.process_execute(command.1.atoms()),
-                CommandKind::Assign => self.process_assign(command.1.atoms()),
+            if !command.trim().is_empty() {
+                retcode = self.run_complete(command, origin, line);
             }
+            line += command.matches('\n').count();
+            start = end + 1;
+        }
+        if !source[start..].trim().is_empty() {
+            retcode = self.run_complete(&source[start..], origin, line);
         }
         retcode
     }
 
-    fn process_execute(&mut self, atoms: Vec<Atom>) -> usize {
-        let mut execs = Vec::new();
-        let mut exec = Executee::new();
-        for atom in atoms {
-            match atom.kind() {
-                AtomKind::Word(word) => exec.arg(word),
-                AtomKind::Pipe => {
-                    execs.push(exec);
-                    exec = Executee::new();
-                },
-                AtomKind::FdDup(src, dst) => exec.fd_duplicate(src, dst),
-                AtomKind::FdMov(src, dst) => exec.fd_move(src, dst),
-                AtomKind::FileWrite(file, fd) => exec.file_write(file, fd),
-                AtomKind::FileAppend(file, fd) => exec.file_append(file, fd),
-                AtomKind::FileRead(file, fd) => exec.file_read(file, fd),
-                AtomKind::FileRW(file, fd) => exec.file_rw(file, fd),
-                AtomKind::StdWrite(file) => {
-                    exec.file_write(file, 1);
-                    exec.fd_duplicate(1, 2);
-                },
-                AtomKind::StdAppend(file) => {
-                    exec.file_append(file, 1);
-                    exec.fd_duplicate(1, 2);
+    /// Parses and runs `source`, a complete command starting on line
+    /// `first_line` of `origin`
+    ///
+    /// Aliases are expanded just before, so those defined by the commands
+    /// run so far apply.
+    fn run_complete(&mut self, source: &str, origin: &str, first_line: usize) -> usize {
+        let source = self.expand_aliases(source, true);
+        match bash::parse(&source, true) {
+            Ok(list) => {
+                let line_base = std::mem::replace(&mut self.line_base, first_line - 1);
+                let retcode = self.run_list(&list);
+                self.line_base = line_base;
+                retcode
+            },
+            Err(mut error) => {
+                error.set_origin(origin, first_line);
+                eprintln!("{}", error);
+                self.status = 2;
+                2
+            },
+        }
+    }
+
+    /// Expands aliases in `source`, as an interactive shell does before
+    /// parsing
+    ///
+    /// The first word of each simple command naming an alias is replaced
+    /// by its value, which is then looked at again, except for aliases it
+    /// came from. A value ending with a blank has the word after it
+    /// expanded too. Expansion stops where the text fails to parse.
+    fn expand_aliases<'a>(&self, source: &'a str, comments: bool) -> Cow<'a, str> {
+        if self.aliases.is_empty() || !self.interactive() {
+            return Cow::Borrowed(source);
+        }
+        let mut text = String::from(source);
+        // Byte ranges of substituted values along with the aliases they
+        // came from, innermost last
+        let mut regions: Vec<(usize, usize, Vec<&str>)> = Vec::new();
+        let mut checked = 0;
+        let mut next_word: Option<usize> = None;
+        while let Ok(commands) = bash::command_words(&text, comments) {
+            let candidate = commands.iter()
+                .flat_map(|words| words.iter().enumerate())
+                .find(|(n, word)| word.start >= checked && (*n == 0 || next_word.is_some_and(|next| word.start >= next)))
+                .map(|(_, word)| *word);
+            let word = match candidate {
+                Some(word) => word,
+                None => break,
+            };
+            if next_word.is_some_and(|next| word.start >= next) {
+                next_word = None;
+            }
+            let mut within = regions.iter()
+                .rev()
+                .find(|(start, end, _)| *start <= word.start && word.end <= *end)
+                .map_or_else(Vec::new, |(_, _, names)| names.clone());
+            let (name, value) = match self.aliases.get_key_value(&text[word.start..word.end]) {
+                Some((name, value)) if !within.contains(&name.as_str()) => (name, value),
+                _ => {
+                    checked = word.end;
+                    continue;
                 },
+            };
+
+            let shift = |offset: &mut usize| *offset = *offset + value.len() - (word.end - word.start);
+            for (start, end, _) in &mut regions {
+                if *start >= word.end {
+                    shift(start);
+                }
+                if *end >= word.end {
+                    shift(end);
+                }
             }
-        };
-        execs.push(exec);
-
-        for exec in &mut execs {
-            let command = exec.args().first();
-            if command.is_some() {
-                let command = command.unwrap();
-                exec.set_kind(match command.as_str() {
-                    "cd" => ExecuteeKind::StrongBuiltin(String::from("cd")),
-                    "state" | "self" => ExecuteeKind::WeakBuiltin(String::from("state")),
-                    other => {
-                        if let Some(path) = self.find_bin(other) {
-                            ExecuteeKind::Binary(path)
-                        } else {
-                            ExecuteeKind::WeakBuiltin(String::from("fail"))
-                        }
-                    }
-                });
+            if let Some(next) = next_word.as_mut().filter(|next| **next >= word.end) {
+                shift(next);
+            }
+            text.replace_range(word.start..word.end, value);
+            within.push(name);
+            regions.push((word.start, word.start + value.len(), within));
+            checked = word.start;
+            if value.ends_with([' ', '\t']) {
+                next_word = Some(word.start + value.len());
             }
         }
+        Cow::Owned(text)
+    }
+
+    /// Runs `list`, returning the status of its last command
+    pub fn run_list(&mut self, list: &List) -> usize {
+        let mut retcode = 0;
+        for and_or in &list.items {
+            retcode = self.run_and_or(and_or);
+        }
+        retcode
+    }
+
+    fn run_and_or(&mut self, and_or: &AndOr) -> usize {
+        let mut retcode = self.run_pipeline(&and_or.first);
+        for (kind, pipeline) in &and_or.rest {
+            match kind {
+                AndOrKind::And if retcode != 0 => continue,
+                AndOrKind::Or if retcode == 0 => continue,
+                _ => retcode = self.run_pipeline(pipeline),
+            }
+        }
+        retcode
+    }
 
-        if execs.len() == 1 {
-            executor::execute_single(&execs[0]) as usize
+    fn run_pipeline(&mut self, pipeline: &Pipeline) -> usize {
+        let retcode = if let [command] = &pipeline.commands[..] {
+            self.run_command(command)
         } else {
+            let mut execs: Vec<_> = pipeline.commands.iter()
+                .map(|command| self.stage(command))
+                .collect();
             executor::execute_group(&mut execs[..]) as usize
+        };
+
+        self.status = if pipeline.negated {
+            (retcode == 0) as usize
+        } else {
+            retcode
+        };
+        self.status
+    }
+
+    /// Runs `command`, forking only where its kind requires it
+    pub fn run_command(&mut self, command: &Command) -> usize {
+        match &command.kind {
+            CommandKind::Simple(simple) => {
+                self.lineno = self.line_base + command.line;
+                let exec = self.executee(simple, &command.redirects);
+                if !self.finish_expansion() {
+                    return 1;
+                }
+                if let Some(arg) = exec.args().last() {
+                    self.last_arg = arg.clone();
+                }
+                if simple.words.is_empty() {
+                    for assign in &simple.assigns {
+                        self.assign(assign);
+                        if !self.finish_expansion() {
+                            return 1;
+                        }
+                    }
+                    if command.redirects.is_empty() {
+                        return 0;
+                    }
+                }
+                // Assignments before a command run in the shell process hold
+                // until it finishes, as they would in its environment.
+                let temporary = match exec.kind() {
+                    ExecuteeKind::StrongBuiltin(_) | ExecuteeKind::Function(_) => simple.assigns.iter()
+                        .filter(|assign| matches!((&assign.value, &assign.subscript), (AssignValue::Word(_), None)))
+                        .filter_map(|assign| Some((assign.name.to_string(), exec.env_var(&assign.name)?.clone())))
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                let saved: Vec<_> = temporary.into_iter()
+                    .map(|(name, value)| {
+                        let old = self.vars.insert(name.clone(), Value::Scalar(value));
+                        (name, old)
+                    })
+                    .collect();
+                let retcode = executor::execute_single(&exec) as usize;
+                for (name, old) in saved.into_iter().rev() {
+                    match old {
+                        Some(old) => self.vars.insert(name, old),
+                        None => self.vars.remove(&name),
+                    };
+                }
+                retcode
+            },
+            CommandKind::Compound(CompoundCommand::Subshell(_)) => {
+                executor::execute_single(&self.stage(command)) as usize
+            },
+            CommandKind::Compound(compound) => {
+                let exec = self.redirected(&command.redirects);
+                executor::in_process(&exec, || self.run_compound(compound) as u8) as usize
+            },
+            CommandKind::FunctionDef(name, body) => {
+                self.functions.insert(name.to_string(), Rc::new((**body).clone().into_owned()));
+                0
+            },
         }
     }
 
-    fn process_assign(&mut self, atoms: Vec<Atom>) -> usize {
-        let args: Vec<_> = atoms.iter()
-            .map(|atom| {
-                if let AtomKind::Word(word) = atom.kind() {
-                    Some(word)
-                } else {
-                    None
+    /// Runs `command` in a child process forked for it, whose redirections
+    /// are already in place
+    pub fn run_forked(&mut self, command: &Command) -> usize {
+        match &command.kind {
+            CommandKind::Compound(CompoundCommand::Subshell(list)) => self.run_list(list),
+            CommandKind::Compound(compound) => self.run_compound(compound),
+            _ => self.run_command(command),
+        }
+    }
+
+    fn run_compound(&mut self, compound: &CompoundCommand) -> usize {
+        match compound {
+            CompoundCommand::Brace(list) | CompoundCommand::Subshell(list) => self.run_list(list),
+            CompoundCommand::If { branches, otherwise } => {
+                for (condition, body) in branches {
+                    if self.run_list(condition) == 0 {
+                        return self.run_list(body);
+                    }
                 }
-            })
-            .filter(Option::is_some)
-            .map(Option::unwrap)
-            .collect();
-        let mut args = args.into_iter();
+                otherwise.as_ref().map_or(0, |body| self.run_list(body))
+            },
+            CompoundCommand::While(condition, body) => {
+                let mut retcode = 0;
+                while self.run_list(condition) == 0 {
+                    retcode = self.run_list(body);
+                }
+                retcode
+            },
+            CompoundCommand::Until(condition, body) => {
+                let mut retcode = 0;
+                while self.run_list(condition) != 0 {
+                    retcode = self.run_list(body);
+                }
+                retcode
+            },
+            CompoundCommand::For(name, words, body) => {
+                let values: Vec<_> = match words {
+                    Some(words) => words.iter()
+                        .flat_map(|word| expand::word(self, word))
+                        .collect(),
+                    None => self.positional.clone(),
+                };
+                if !self.finish_expansion() {
+                    return 1;
+                }
+                let mut retcode = 0;
+                for value in values {
+                    self.set_var(name, value);
+                    retcode = self.run_list(body);
+                }
+                retcode
+            },
+            CompoundCommand::Conditional(condition) => {
+                let result = self.condition(condition);
+                if !self.finish_expansion() {
+                    return 1;
+                }
+                match result {
+                    Ok(result) => !result as usize,
+                    Err(message) => {
+                        error!("rush: {}", message);
+                        2
+                    },
+                }
+            },
+        }
+    }
 
-        self.vars.insert(args.next().unwrap(), args.next().unwrap());
-        0
+    /// Evaluates `condition` of a `[[ ]]` command
+    ///
+    /// Words are expanded without splitting. The right side of `==` and
+    /// `!=` is a pattern and that of `=~` a regular expression, the match
+    /// of which is stored in `BASH_REMATCH` along with its groups.
+    fn condition(&mut self, condition: &Condition) -> Result<bool, String> {
+        match condition {
+            Condition::Word(word) => Ok(!expand::string(self, word).is_empty()),
+            Condition::Unary(op, operand) => Ok(condition::unary(self, op, &expand::string(self, operand))),
+            Condition::Binary(lhs, op, rhs) => {
+                let lhs = expand::string(self, lhs);
+                match op.as_ref() {
+                    "=" | "==" => Ok(glob::matches(&expand::pattern(self, rhs), &lhs)),
+                    "!=" => Ok(!glob::matches(&expand::pattern(self, rhs), &lhs)),
+                    "=~" => self.match_regex(&lhs, &expand::regex(self, rhs)),
+                    "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+                        let rhs = expand::string(self, rhs);
+                        condition::binary(&self.integer_operand(lhs), op, &self.integer_operand(rhs))
+                    },
+                    op => condition::binary(&lhs, op, &expand::string(self, rhs)),
+                }
+            },
+            Condition::Not(condition) => Ok(!self.condition(condition)?),
+            Condition::And(lhs, rhs) => Ok(self.condition(lhs)? && self.condition(rhs)?),
+            Condition::Or(lhs, rhs) => Ok(self.condition(lhs)? || self.condition(rhs)?),
+        }
+    }
+
+    /// Resolves an operand of an integer comparison, which may name a
+    /// variable holding the integer, unset ones counting as 0
+    fn integer_operand(&self, operand: String) -> String {
+        if builtin::valid_name(&operand) {
+            self.var(&operand).cloned().unwrap_or_else(|| String::from("0"))
+        } else {
+            operand
+        }
+    }
+
+    /// Matches `text` against regular expression `regex`, setting
+    /// `BASH_REMATCH` to the match and its groups
+    fn match_regex(&mut self, text: &str, regex: &str) -> Result<bool, String> {
+        let regex = regex::Regex::new(regex)
+            .map_err(|_| format!("{}: Invalid regular expression.", regex))?;
+        let groups: BTreeMap<_, _> = regex.captures(text)
+            .map(|captures| captures.iter()
+                .map(|group| group.map_or_else(String::new, |group| String::from(group.as_str())))
+                .enumerate()
+                .collect())
+            .unwrap_or_default();
+        let matched = !groups.is_empty();
+        self.set_value("BASH_REMATCH", Value::Indexed(groups));
+        Ok(matched)
+    }
+
+    /// Calls function `name` with `args`, the first of which is the
+    /// function name itself
+    pub fn call_function(&mut self, name: &str, args: &[String]) -> usize {
+        let body = match self.functions.get(name) {
+            Some(body) => body.clone(),
+            None => return 127,
+        };
+        let positional = self.set_positional(args[1..].to_vec());
+        let retcode = self.run_command(&body);
+        self.set_positional(positional);
+        retcode
+    }
+
+    /// Prepares an executee for `command` run as a stage of a pipeline
+    fn stage(&self, command: &Command) -> Executee {
+        match &command.kind {
+            CommandKind::Simple(simple) => self.executee(simple, &command.redirects),
+            _ => {
+                let mut exec = self.redirected(&command.redirects);
+                exec.set_kind(ExecuteeKind::Compound(Box::new(command.clone().into_owned())));
+                exec
+            },
+        }
+    }
+
+    /// Expands `simple` into an executee, resolving what it runs
+    fn executee(&self, simple: &SimpleCommand, redirects: &[Redirect]) -> Executee {
+        let mut exec = self.redirected(redirects);
+        for word in &simple.words {
+            for field in expand::word(self, word) {
+                exec.arg(field);
+            }
+        }
+
+        for name in &self.exports {
+            if let Some(Value::Indexed(_) | Value::Associative(_)) = self.vars.get(name) {
+                continue;
+            }
+            if let Some(value) = self.param(name) {
+                exec.var(name.clone(), value);
+            }
+        }
+        // Arrays cannot be passed in the environment. Without a command,
+        // `assign` makes the assignments instead, expanding them itself.
+        for assign in simple.assigns.iter().filter(|_| !simple.words.is_empty()) {
+            if let (AssignValue::Word(value), None) = (&assign.value, &assign.subscript) {
+                exec.var(assign.name.to_string(), expand::string(self, value));
+            }
+        }
+
+        if !exec.args().is_empty() {
+            let (kind, args) = self.resolve(exec.args());
+            exec.set_kind(kind);
+            if let Some(args) = args {
+                exec.set_args(args);
+            }
+        }
+        exec
+    }
+
+    /// Creates an executee carrying just `redirects`
+    ///
+    /// With `noclobber` set, `>` only writes files that are not regular
+    /// files already, unlike `>|`.
+    fn redirected(&self, redirects: &[Redirect]) -> Executee {
+        let mut exec = Executee::new();
+        let noclobber = self.flag('C');
+        let write = |exec: &mut Executee, file, fd| if noclobber {
+            exec.file_create(file, fd)
+        } else {
+            exec.file_write(file, fd)
+        };
+        for redirect in redirects {
+            match redirect {
+                Redirect::FdDup(src, dst) => exec.fd_duplicate(*src, *dst),
+                Redirect::FdMov(src, dst) => exec.fd_move(*src, *dst),
+                Redirect::FdClose(fd) => exec.fd_close(*fd),
+                Redirect::FileWrite(file, fd) => write(&mut exec, expand::string(self, file), *fd),
+                Redirect::FileClobber(file, fd) => exec.file_write(expand::string(self, file), *fd),
+                Redirect::FileAppend(file, fd) => exec.file_append(expand::string(self, file), *fd),
+                Redirect::FileRead(file, fd) => exec.file_read(expand::string(self, file), *fd),
+                Redirect::FileRW(file, fd) => exec.file_rw(expand::string(self, file), *fd),
+                Redirect::StdWrite(file) => {
+                    write(&mut exec, expand::string(self, file), 1);
+                    exec.fd_duplicate(1, 2);
+                },
+                Redirect::StdAppend(file) => {
+                    exec.file_append(expand::string(self, file), 1);
+                    exec.fd_duplicate(1, 2);
+                },
+            }
+        }
+        exec
     }
 
     pub fn prompt(&self) -> String {
         self.prompt.clone()
     }
 
-    pub fn line(&self) -> String {
+    /// Returns the prompt shown on continuation lines, `PS2` if set
+    pub fn continuation_prompt(&self) -> String {
+        self.var("PS2")
+            .cloned()
+            .unwrap_or_else(|| self.config.continuation_prompt.clone())
+    }
+
+    pub fn line(&self) -> &str {
         self.input.raw()
     }
 
@@ -244,13 +1089,91 @@ impl Shell {
         self.input.set(line);
     }
 
-    fn find_bin(&self, command: &str) -> Option<PathBuf> {
-        for path in &self.bin_dirs {
-            let path: PathBuf = [path, command].iter().collect();
-            if let Ok(_) = stat::stat(&path) {
-                return Some(path);
+    /// Lists what command `name` could run, in the order they are looked
+    /// up: a function, a builtin and binaries found in `PATH`
+    ///
+    /// The first is what runs. Binaries are only searched for as far as
+    /// the iterator is consumed, in each directory once.
+    pub fn candidates<'a>(&'a self, name: &'a str) -> impl Iterator<Item = ExecuteeKind> + 'a {
+        let function = self.functions.contains_key(name)
+            .then(|| ExecuteeKind::Function(String::from(name)));
+        let mut searched = HashSet::new();
+        function.into_iter()
+            .chain(builtin::kind(name))
+            .chain(self.bin_dirs.iter()
+                .filter(move |dir| searched.insert(*dir))
+                .filter_map(move |dir| bin_in(dir, name))
+                .map(ExecuteeKind::Binary))
+    }
+
+    /// Decides what runs for the command word of `args`
+    ///
+    /// Besides the kind of the executee, returns replacement arguments when
+    /// the command gets rewritten, as for auto-cd, the not-found handler
+    /// or `command`, which runs the rest of its arguments skipping
+    /// functions.
+    fn resolve(&self, args: &[String]) -> (ExecuteeKind, Option<Vec<String>>) {
+        if args[0] == "command" {
+            let rest = match &args[1..] {
+                [dashes, rest @ ..] if dashes == "--" => rest,
+                rest => rest,
+            };
+            if rest.first().is_some_and(|arg| !arg.starts_with('-')) {
+                let (kind, args) = self.resolve_from(rest, false);
+                return (kind, args.or_else(|| Some(rest.to_vec())));
+            }
+        }
+        self.resolve_from(args, true)
+    }
+
+    /// Resolves `args` like `resolve`, looking at functions if `functions`
+    fn resolve_from(&self, args: &[String], functions: bool) -> (ExecuteeKind, Option<Vec<String>>) {
+        let command = &args[0];
+        let found = self.candidates(command)
+            .find(|kind| functions || !matches!(kind, ExecuteeKind::Function(_)));
+        if let Some(kind) = found {
+            return (kind, None);
+        }
+
+        if self.config.auto_cd && args.len() == 1 && Path::new(command).is_dir() {
+            let args = vec![String::from("cd"), command.clone()];
+            return (ExecuteeKind::StrongBuiltin(String::from("cd")), Some(args));
+        }
+
+        if self.functions.contains_key("command_not_found_handle") {
+            let handler = String::from("command_not_found_handle");
+            let args = std::iter::once(handler.clone()).chain(args.iter().cloned()).collect();
+            return (ExecuteeKind::Function(handler), Some(args));
+        }
+        if let Some(handler) = &self.config.command_not_found {
+            if let Some(path) = self.find_bin(handler) {
+                let args = std::iter::once(handler.clone()).chain(args.iter().cloned()).collect();
+                return (ExecuteeKind::Binary(path), Some(args));
             }
         }
+
+        (ExecuteeKind::WeakBuiltin(String::from("fail")), None)
+    }
+
+    fn find_bin(&self, command: &str) -> Option<PathBuf> {
+        self.bin_dirs.iter().find_map(|dir| bin_in(dir, command))
+    }
+}
+
+/// Returns the path of executable `command` in directory `dir`, if there
+/// is one
+fn bin_in(dir: &str, command: &str) -> Option<PathBuf> {
+    let path: PathBuf = [dir, command].iter().collect();
+    let stat = stat::stat(&path).ok()?;
+    let kind = SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT;
+    if kind != SFlag::S_IFDIR && unistd::access(&path, AccessFlags::X_OK).is_ok() {
+        Some(path)
+    } else {
         None
     }
 }
+
+/// Returns the time since the Unix epoch
+fn now() -> std::time::Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Value of a shell variable
+#[derive(Clone, Debug)]
+pub enum Value {
+    Scalar(String),
+    /// Array indexed by numbers, which may have gaps
+    Indexed(BTreeMap<usize, String>),
+    /// Array indexed by strings
+    Associative(BTreeMap<String, String>),
+}
+
+impl Value {
+    /// Returns the value standing for the whole variable where a single
+    /// string is needed, which is element 0 for arrays
+    pub fn scalar(&self) -> Option<&String> {
+        match self {
+            Value::Scalar(value) => Some(value),
+            Value::Indexed(items) => items.get(&0),
+            Value::Associative(items) => items.get("0"),
+        }
+    }
+
+    /// Returns the element at `key`
+    ///
+    /// Keys of indexed arrays are numbers, negative ones counting back
+    /// from the end. A scalar is an array of just element 0.
+    pub fn get(&self, key: &str) -> Option<&String> {
+        match self {
+            Value::Scalar(value) => Some(value).filter(|_| self.index(key) == Some(0)),
+            Value::Indexed(items) => items.get(&self.index(key)?),
+            Value::Associative(items) => items.get(key),
+        }
+    }
+
+    /// Sets the element at `key`, turning a scalar into an indexed array
+    ///
+    /// Returns `false` if `key` is not a valid index.
+    pub fn set(&mut self, key: &str, value: String) -> bool {
+        let index = self.index(key);
+        if let Value::Scalar(scalar) = self {
+            let scalar = std::mem::take(scalar);
+            *self = Value::Indexed(BTreeMap::from([(0, scalar)]));
+        }
+        match self {
+            Value::Indexed(items) => match index {
+                Some(index) => {
+                    items.insert(index, value);
+                    true
+                },
+                None => false,
+            },
+            Value::Associative(items) => {
+                items.insert(String::from(key), value);
+                true
+            },
+            Value::Scalar(_) => unreachable!(),
+        }
+    }
+
+    /// Returns the values of all elements in order
+    pub fn values(&self) -> Vec<String> {
+        match self {
+            Value::Scalar(value) => vec![value.clone()],
+            Value::Indexed(items) => items.values().cloned().collect(),
+            Value::Associative(items) => items.values().cloned().collect(),
+        }
+    }
+
+    /// Returns the keys of all elements in order
+    pub fn keys(&self) -> Vec<String> {
+        match self {
+            Value::Scalar(_) => vec![String::from("0")],
+            Value::Indexed(items) => items.keys().map(usize::to_string).collect(),
+            Value::Associative(items) => items.keys().cloned().collect(),
+        }
+    }
+
+    /// Returns the index following the last element of an indexed array
+    pub fn next_index(&self) -> usize {
+        match self {
+            Value::Scalar(_) => 1,
+            Value::Indexed(items) => items.keys().next_back().map_or(0, |last| last + 1),
+            Value::Associative(_) => 0,
+        }
+    }
+
+    /// Resolves `key` to an index, counting negative keys from the end
+    fn index(&self, key: &str) -> Option<usize> {
+        let index: i64 = key.trim().parse().ok()?;
+        if index < 0 {
+            usize::try_from(self.next_index() as i64 + index).ok()
+        } else {
+            Some(index as usize)
+        }
+    }
+}
+
+/// Formats the value so that assigning it reads it back in
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let items: Vec<_> = match self {
+            Value::Scalar(value) => return write!(f, "{}", quote(value)),
+            Value::Indexed(items) => items.iter()
+                .map(|(index, value)| format!("[{}]={}", index, quote(value)))
+                .collect(),
+            Value::Associative(items) => items.iter()
+                .map(|(key, value)| format!("[{}]={}", quote(key), quote(value)))
+                .collect(),
+        };
+        write!(f, "({})", items.join(" "))
+    }
+}
+
+/// Quotes `text` for the shell to read it back as a single word, leaving
+/// words that need no quotes as they are
+pub fn quote(text: &str) -> String {
+    let plain = !text.is_empty() && text.chars()
+        .all(|c| c.is_alphanumeric() || "_@%+=:,./-".contains(c));
+    if plain {
+        String::from(text)
+    } else {
+        single_quote(text)
+    }
+}
+
+/// Quotes `text` in single quotes, as `${name@Q}` does, whatever it holds
+pub fn single_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
@@ -0,0 +1,54 @@
+//! Builtins changing the state of the shell process
+
+mod common;
+
+use common::{rush, stdout};
+
+#[test]
+fn exit_runs_exit_trap() {
+    let output = rush("trap 'echo bye $?' EXIT; echo body; exit 3; echo after");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "body\nbye 3\n");
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn end_of_input_runs_exit_trap() {
+    let output = rush("trap 'echo bye' 0; false");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "bye\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn exit_trap_may_exit_itself() {
+    assert_eq!(rush("trap 'exit 9' EXIT; exit 3").status.code(), Some(9));
+}
+
+#[test]
+fn exit_trap_not_inherited_by_subshells() {
+    assert_eq!(stdout("trap 'echo bye' EXIT; (exit 4); echo $?"), "4\nbye\n");
+    assert_eq!(stdout("(trap 'echo inner' EXIT; echo sub); echo outer"), "sub\ninner\nouter\n");
+}
+
+#[test]
+fn exit_trap_reset() {
+    assert_eq!(stdout("trap 'echo bye' EXIT; trap - EXIT; trap"), "");
+}
+
+#[test]
+fn moved_descriptor_restored_after_builtin() {
+    assert_eq!(stdout("cd . 3>&1-; echo still"), "still\n");
+    assert_eq!(stdout("echo moved 4>&1- >&4; echo again"), "moved\nagain\n");
+}
+
+#[test]
+fn invalid_unicode_escape_kept() {
+    assert_eq!(stdout(r"printf '[\ud800][\U00110000]\n'; echo -e '[\ud834]'"), "[\\ud800][\\U00110000]\n[\\ud834]\n");
+}
+
+#[test]
+fn export_and_unset() {
+    let script = "export A=1 B; B=2; sh -c 'echo $A $B'; unset A; sh -c 'echo \"<$A>\"'; echo \"<$A>\"";
+    assert_eq!(stdout(script), "1 2\n<>\n<>\n");
+    assert_eq!(stdout("C=x; export C; export -p | grep ' C='"), "export C=x\n");
+    assert_eq!(stdout("f() { echo f; }; unset -f f; type f >/dev/null 2>&1 || echo gone"), "gone\n");
+}
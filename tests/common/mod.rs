@@ -0,0 +1,37 @@
+//! Running the shell from integration tests
+//!
+//! Not every test uses every helper.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Runs `script` with `rush -c`, without reading any config
+pub fn rush(script: &str) -> Output {
+    rush_args(script, &[])
+}
+
+/// Runs `script` with `rush -c`, passing `args` as `$0` and the positional
+/// parameters
+pub fn rush_args(script: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(["--config", "/dev/null", "-c", script])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Returns what `script` prints to standard output
+pub fn stdout(script: &str) -> String {
+    String::from_utf8(rush(script).stdout).unwrap()
+}
+
+/// Returns what `script` run with `args` prints to standard output
+pub fn stdout_args(script: &str, args: &[&str]) -> String {
+    String::from_utf8(rush_args(script, args).stdout).unwrap()
+}
+
+/// Returns a path to a file private to test `name`
+pub fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rush-{}-{}", name, std::process::id()))
+}
@@ -0,0 +1,38 @@
+//! Expansion of parameters in words
+
+mod common;
+
+use common::{rush_args, stdout_args};
+
+#[test]
+fn positional_with_leading_zeros() {
+    assert_eq!(stdout_args("echo ${00} ${000} ${01}", &["name", "first"]), "name name first\n");
+}
+
+#[test]
+fn positional_past_the_end() {
+    assert_eq!(stdout_args("echo \"<${3}>\" \"<$9>\"", &["name", "first", "second"]), "<> <>\n");
+}
+
+#[test]
+fn quoted_plain_word() {
+    assert_eq!(stdout_args("x=hi; echo ${x@Q}", &[]), "'hi'\n");
+}
+
+#[test]
+fn quoted_single_quote() {
+    assert_eq!(stdout_args("x=\"it's\"; echo ${x@Q}", &[]), "'it'\\''s'\n");
+}
+
+#[test]
+fn substring_offset_before_start() {
+    assert_eq!(stdout_args("v=abc; echo \"<${v: -10}>\" \"<${v: -2}>\" \"<${v:5}>\"", &[]), "<> <bc> <>\n");
+}
+
+#[test]
+fn substring_length_before_offset() {
+    let output = rush_args("v=abcd; echo \"<${v:1:-1}>\"; echo \"<${v:2:-3}>\"; echo after", &[]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "<bc>\n");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "rush: -3: Substring expression < 0.\n");
+    assert_eq!(output.status.code(), Some(1));
+}
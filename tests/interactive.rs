@@ -0,0 +1,38 @@
+//! Interactive shells reading from standard input that is not a terminal
+
+mod common;
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+use common::temp_file;
+
+/// Runs `rush -i` with `args`, feeding it `input`
+fn interactive(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(["--config", "/dev/null", "-i"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn forced_interactive_without_terminal() {
+    let output = interactive(&["--norc"], "echo hi\nif true\nthen echo yes\nfi\nexit 3\n");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "hi\nyes\n");
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn alias_applies_later_in_rc_file() {
+    let rcfile = temp_file("rcfile");
+    std::fs::write(&rcfile, "alias hi='echo hello'\nhi\n").unwrap();
+    let output = interactive(&["--rcfile", rcfile.to_str().unwrap()], "hi\n");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "hello\nhello\n");
+    std::fs::remove_file(rcfile).unwrap();
+}
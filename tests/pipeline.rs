@@ -0,0 +1,63 @@
+//! Redirections of commands in pipelines
+//!
+//! Each stage is connected to its pipes before its own redirections apply,
+//! so that those can refer to the pipes, as `2>&1 |` does.
+
+mod common;
+
+use common::{rush, stdout, temp_file};
+
+#[test]
+fn stderr_duplicated_into_pipe() {
+    assert_eq!(stdout("{ echo out; echo err >&2; } 2>&1 | sort"), "err\nout\n");
+}
+
+#[test]
+fn stdout_redirected_after_dup() {
+    let output = rush("{ echo out; echo err >&2; } 2>&1 >/dev/null | sed 's/^/piped /'");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "piped err\n");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn stdout_redirected_away_from_pipe() {
+    let file = temp_file("away");
+    let script = format!("echo out >{} | echo next; cat {}", file.display(), file.display());
+    assert_eq!(stdout(&script), "next\nout\n");
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn stdin_redirected_away_from_pipe() {
+    let file = temp_file("stdin");
+    std::fs::write(&file, "file\n").unwrap();
+    let script = format!("echo piped | cat <{} | cat", file.display());
+    assert_eq!(stdout(&script), "file\n");
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn middle_stage_redirects() {
+    let script = "echo a | { cat; echo b >&2; } 2>&1 | sed 's/^/> /'";
+    assert_eq!(stdout(script), "> a\n> b\n");
+}
+
+#[test]
+fn pipe_stderr() {
+    let output = rush("{ echo out; echo err >&2; } |& sort");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "err\nout\n");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn pipe_stderr_after_own_redirects() {
+    assert_eq!(stdout("{ echo out; echo err >&2; } 2>/dev/null |& cat"), "out\nerr\n");
+    assert_eq!(stdout("{ echo out; echo err >&2; } >/dev/null |& cat"), "");
+}
+
+#[test]
+fn pipe_stderr_across_stages() {
+    let script = "{ echo a; echo b >&2; } |& { cat; echo c >&2; } |& sort";
+    assert_eq!(stdout(script), "a\nb\nc\n");
+}
+
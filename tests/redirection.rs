@@ -0,0 +1,20 @@
+//! Redirections of single commands
+
+mod common;
+
+use common::{rush, stdout, temp_file};
+
+#[test]
+fn bad_descriptor_named() {
+    let output = rush("exec 3>&-; echo x >&3");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "rush: 3: Bad file number.\n");
+}
+
+#[test]
+fn read_write_keeps_contents() {
+    let file = temp_file("rw");
+    std::fs::write(&file, "kept\n").unwrap();
+    assert_eq!(stdout(&format!("exec 3<>{}; cat <&3", file.display())), "kept\n");
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "kept\n");
+    std::fs::remove_file(file).unwrap();
+}
@@ -0,0 +1,43 @@
+//! Startup and sourced files, which run one command at a time
+
+mod common;
+
+use std::process::Command;
+
+use common::{rush, temp_file};
+
+#[test]
+fn syntax_error_skips_one_command() {
+    let file = temp_file("syntax");
+    std::fs::write(&file, "echo one\nbad (\necho two\n").unwrap();
+    let output = rush(&format!(". {}; echo after", file.display()));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "one\ntwo\nafter\n");
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with(&format!("{}:2:5: Syntax error", file.display())));
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn failed_expansion_keeps_login_shell() {
+    let home = temp_file("home");
+    std::fs::create_dir_all(&home).unwrap();
+    std::fs::write(home.join(".profile"), "echo ${unset_in_profile:?}\necho next\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(["--config", "/dev/null", "-l", "-c", "echo main"])
+        .env("HOME", &home)
+        .output()
+        .unwrap();
+    // Whatever `/etc/profile` does comes first.
+    assert!(String::from_utf8(output.stdout).unwrap().ends_with("next\nmain\n"));
+    assert_eq!(output.status.code(), Some(0));
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn runtime_error_located() {
+    let file = temp_file("located");
+    std::fs::write(&file, "echo one\n\nno_such_command_here\n").unwrap();
+    let output = rush(&format!(". {}; no_such_command_here", file.display()));
+    let expected = format!("{}:3: rush: Unknown command no_such_command_here.\nrush: Unknown command no_such_command_here.\n", file.display());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), expected);
+    std::fs::remove_file(file).unwrap();
+}